@@ -0,0 +1,214 @@
+//! Validation of tarball entries before they are written to disk.
+//!
+//! Releases come from a registry or a mirror, so an entry must not be able to write outside the
+//! destination directory: paths are checked for traversal, and only plain files and directories
+//! are extracted.
+
+use std::{
+    fs,
+    io::{self, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
+};
+use tar::{Entry, EntryType};
+
+/// Largest file extracted from a release.
+pub const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Why an entry is not extracted.
+#[derive(Debug, thiserror::Error)]
+pub enum Rejection {
+    #[error("path is not valid on this platform")]
+    InvalidPath,
+    #[error("absolute path")]
+    Absolute,
+    #[error("path escapes the destination")]
+    Traversal,
+    #[error("link to {0}")]
+    Link(String),
+    #[error("unsupported entry type {0:?}")]
+    Unsupported(EntryType),
+    #[error("{0} bytes exceed the size limit")]
+    TooLarge(u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{path}: {reason}")]
+pub struct RejectedEntry {
+    pub path: String,
+    pub reason: Rejection,
+}
+
+/// Check that `entry` is a file or directory which stays inside the destination, returning its
+/// path with `.` components removed.
+pub fn check_entry<R: Read>(entry: &Entry<R>) -> Result<PathBuf, RejectedEntry> {
+    let rejected = |reason| RejectedEntry {
+        path: String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+        reason,
+    };
+    let header = entry.header();
+    match header.entry_type() {
+        EntryType::Regular | EntryType::Continuous | EntryType::Directory => {}
+        EntryType::Symlink | EntryType::Link => {
+            let target = entry
+                .link_name_bytes()
+                .map(|name| String::from_utf8_lossy(&name).into_owned())
+                .unwrap_or_default();
+            return Err(rejected(Rejection::Link(target)));
+        }
+        other => return Err(rejected(Rejection::Unsupported(other))),
+    }
+    if entry.size() > MAX_ENTRY_SIZE {
+        return Err(rejected(Rejection::TooLarge(entry.size())));
+    }
+
+    let path = entry.path().map_err(|_| rejected(Rejection::InvalidPath))?;
+    check_path(&path).map_err(rejected)
+}
+
+/// Check that the relative `path` stays inside the directory it is joined onto, returning it with
+/// `.` components removed.
+pub fn check_path(path: &Path) -> Result<PathBuf, Rejection> {
+    let mut checked = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => checked.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(Rejection::Traversal),
+            Component::RootDir | Component::Prefix(_) => return Err(Rejection::Absolute),
+        }
+    }
+    if checked.as_os_str().is_empty() {
+        return Err(Rejection::InvalidPath);
+    }
+    Ok(checked)
+}
+
+/// Write an entry accepted by [`check_entry`] to `dest`, replacing the file there.
+pub fn unpack<R: Read>(entry: &mut Entry<R>, dest: &Path) -> io::Result<()> {
+    if entry.header().entry_type() == EntryType::Directory {
+        return fs::create_dir_all(dest);
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // never write through whatever is at `dest`
+    match fs::remove_file(dest) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(dest)?;
+    io::copy(&mut entry.take(MAX_ENTRY_SIZE), &mut file)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = entry.header().mode().unwrap_or(0o644) & 0o755;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    let now = filetime::FileTime::now();
+    filetime::set_file_times(dest, now, now)
+}
+
+/// Fail with the rejected entries, if there are any.
+pub fn ensure_none_rejected(rejected: &[RejectedEntry]) -> eyre::Result<()> {
+    if rejected.is_empty() {
+        return Ok(());
+    }
+    let entries: Vec<_> = rejected.iter().map(|entry| entry.to_string()).collect();
+    eyre::bail!(
+        "{} unsafe entries in the archive: {}",
+        rejected.len(),
+        entries.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llc_rs::test_support::TempDir;
+
+    /// An uncompressed tarball with raw entry names, which `tar::Builder` would refuse.
+    fn tarball(entries: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, entry_type, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if entry_type.is_symlink() || entry_type.is_hard_link() {
+                header.set_size(0);
+                header.as_old_mut().linkname[..content.len()].copy_from_slice(content);
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            } else {
+                header.set_size(content.len() as u64);
+                header.set_cksum();
+                builder.append(&header, *content).unwrap();
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_check_entry() {
+        let data = tarball(&[
+            ("package/./Info/version.json", EntryType::Regular, b"{}"),
+            ("package/Font", EntryType::Directory, b""),
+            ("package/../../evil.dll", EntryType::Regular, b"evil"),
+            ("/etc/passwd", EntryType::Regular, b"evil"),
+            ("package/link", EntryType::Symlink, b"/etc/passwd"),
+            ("package/hardlink", EntryType::Link, b"/etc/passwd"),
+            ("package/dev", EntryType::Char, b""),
+        ]);
+        let mut archive = tar::Archive::new(data.as_slice());
+        let checked: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| check_entry(&entry.unwrap()))
+            .collect();
+
+        assert_eq!(
+            checked[0].as_ref().unwrap(),
+            Path::new("package/Info/version.json")
+        );
+        assert_eq!(checked[1].as_ref().unwrap(), Path::new("package/Font"));
+        let reasons: Vec<_> = checked[2..]
+            .iter()
+            .map(|checked| &checked.as_ref().unwrap_err().reason)
+            .collect();
+        assert!(matches!(reasons[0], Rejection::Traversal));
+        assert!(matches!(reasons[1], Rejection::Absolute));
+        assert!(matches!(reasons[2], Rejection::Link(target) if target == "/etc/passwd"));
+        assert!(matches!(reasons[3], Rejection::Link(_)));
+        assert!(matches!(
+            reasons[4],
+            Rejection::Unsupported(EntryType::Char)
+        ));
+        assert_eq!(
+            checked[2].as_ref().unwrap_err().path,
+            "package/../../evil.dll"
+        );
+    }
+
+    #[test]
+    fn test_unpack() {
+        let dir = TempDir::new("llc-launcher-unpack");
+        let data = tarball(&[("package/a/b.txt", EntryType::Regular, b"llc")]);
+        let mut archive = tar::Archive::new(data.as_slice());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let path = check_entry(&entry).unwrap();
+
+        // a link at the destination is replaced instead of written through
+        let dest = dir.join(&path);
+        let outside = dir.join("outside.txt");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&outside, b"outside").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, &dest).unwrap();
+        unpack(&mut entry, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"llc");
+        assert_eq!(fs::read(&outside).unwrap(), b"outside");
+    }
+}
@@ -15,6 +15,9 @@ pub struct LauncherConfig {
     log_level: tracing::Level,
     #[serde(default = "default_true")]
     telemetry: bool,
+    /// Skip all update checks and launch the installed version.
+    #[serde(default)]
+    offline: bool,
 }
 
 impl Default for LauncherConfig {
@@ -23,6 +26,7 @@ impl Default for LauncherConfig {
             uuid: Uuid::new_v4(),
             log_level: tracing::Level::INFO,
             telemetry: true,
+            offline: false,
         }
     }
 }
@@ -45,6 +49,11 @@ impl LauncherConfig {
     pub fn telemetry(&self) -> bool {
         self.telemetry
     }
+
+    #[inline]
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
 }
 
 pub fn load(dirs: &ProjectDirs) -> (LauncherConfig, LLCConfig) {
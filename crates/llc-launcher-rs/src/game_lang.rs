@@ -0,0 +1,197 @@
+//! The game's language selection, `config.json` in its `Lang` directory.
+//!
+//! The game only loads a language folder once it is selected there. The selection from before
+//! the launcher took over is remembered in the data directory, so that it can be restored on
+//! uninstall.
+
+use llc_rs::LanguagePack;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Language config of the game, in its `Lang` directory.
+pub const GAME_LANG_CONFIG: &str = "config.json";
+
+/// Selection from before a pack was selected, in the launcher's data directory.
+const PREVIOUS_LANG_FILE: &str = "previous-lang.json";
+
+/// `config.json` of the game, keeping the fields the launcher doesn't know about.
+#[derive(Debug)]
+pub struct GameLangConfig {
+    path: PathBuf,
+    fields: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PreviousSelection {
+    lang: Option<String>,
+}
+
+impl GameLangConfig {
+    /// Read the config in `lang_dir`, an empty one if the game has none.
+    pub fn load(lang_dir: &Path) -> eyre::Result<Self> {
+        let path = lang_dir.join(GAME_LANG_CONFIG);
+        let fields = match fs::read(&path) {
+            // editors like Notepad may have saved it with a BOM
+            Ok(content) => {
+                serde_json::from_slice(content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&content))?
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Map::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(GameLangConfig { path, fields })
+    }
+
+    /// Folder of the selected language.
+    pub fn lang(&self) -> Option<&str> {
+        self.fields.get("lang").and_then(Value::as_str)
+    }
+
+    pub fn set_lang(&mut self, lang: Option<&str>) {
+        match lang {
+            Some(lang) => self.fields.insert("lang".to_string(), lang.into()),
+            // `remove` would move the last field in its place
+            None => self.fields.shift_remove("lang"),
+        };
+    }
+
+    /// Write the config back, removing the file once nothing is left in it.
+    pub fn save(&self) -> eyre::Result<()> {
+        if self.fields.is_empty() {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => return Ok(()),
+            }
+        }
+        fs::write(&self.path, serde_json::to_vec(&self.fields)?)?;
+        Ok(())
+    }
+}
+
+/// Select `pack` in the game. The current selection is remembered unless it is one of `packs`.
+pub fn select_pack(
+    lang_dir: &Path,
+    data_dir: &Path,
+    pack: &LanguagePack,
+    packs: &[LanguagePack],
+) -> eyre::Result<()> {
+    let mut config = GameLangConfig::load(lang_dir)?;
+    if config.lang() == Some(pack.lang_dir()) {
+        return Ok(());
+    }
+    if !is_pack(config.lang(), packs) {
+        let previous = PreviousSelection {
+            lang: config.lang().map(str::to_string),
+        };
+        fs::write(
+            data_dir.join(PREVIOUS_LANG_FILE),
+            serde_json::to_vec(&previous)?,
+        )?;
+    }
+    info!("Selecting {} in the game", pack.lang_dir());
+    config.set_lang(Some(pack.lang_dir()));
+    config.save()
+}
+
+/// Restore the selection from before a pack was selected, if one of `packs` is selected.
+pub fn restore_previous(
+    lang_dir: &Path,
+    data_dir: &Path,
+    packs: &[LanguagePack],
+) -> eyre::Result<()> {
+    let previous_path = data_dir.join(PREVIOUS_LANG_FILE);
+    let mut config = GameLangConfig::load(lang_dir)?;
+    if is_pack(config.lang(), packs) {
+        let previous = match fs::read(&previous_path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => PreviousSelection::default(),
+            Err(e) => return Err(e.into()),
+        };
+        // a pack is removed along with the one selected
+        let lang = previous.lang.filter(|lang| !is_pack(Some(lang), packs));
+        info!("Restoring the game language to {lang:?}");
+        config.set_lang(lang.as_deref());
+        config.save()?;
+    }
+    match fs::remove_file(&previous_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn is_pack(lang: Option<&str>, packs: &[LanguagePack]) -> bool {
+    packs.iter().any(|pack| Some(pack.lang_dir()) == lang)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llc_rs::{FontRequirement, test_support::TempDir};
+
+    #[test]
+    fn test_select_and_restore() {
+        let dir = TempDir::new("llc-launcher-game-lang");
+        let packs = [
+            LanguagePack::new("@example/llc-a", "LLC_a", FontRequirement::None),
+            LanguagePack::new("@example/llc-b", "LLC_b", FontRequirement::None),
+        ];
+        let read = || fs::read_to_string(dir.join(GAME_LANG_CONFIG)).unwrap();
+        fs::write(
+            dir.join(GAME_LANG_CONFIG),
+            r#"{"lang":"custom","volume":1}"#,
+        )
+        .unwrap();
+
+        select_pack(&dir, &dir, &packs[0], &packs).unwrap();
+        assert_eq!(read(), r#"{"lang":"LLC_a","volume":1}"#);
+        // switching packs keeps the selection from before the launcher
+        select_pack(&dir, &dir, &packs[1], &packs).unwrap();
+        assert_eq!(read(), r#"{"lang":"LLC_b","volume":1}"#);
+        restore_previous(&dir, &dir, &packs).unwrap();
+        assert_eq!(read(), r#"{"lang":"custom","volume":1}"#);
+        assert!(!dir.join(PREVIOUS_LANG_FILE).exists());
+
+        // nothing was selected before, so nothing is left
+        fs::remove_file(dir.join(GAME_LANG_CONFIG)).unwrap();
+        select_pack(&dir, &dir, &packs[0], &packs).unwrap();
+        assert_eq!(read(), r#"{"lang":"LLC_a"}"#);
+        restore_previous(&dir, &dir, &packs).unwrap();
+        assert!(!dir.join(GAME_LANG_CONFIG).exists());
+    }
+
+    #[test]
+    fn test_select_keeps_user_config() {
+        let dir = TempDir::new("llc-launcher-game-lang-user");
+        let packs = [LanguagePack::new(
+            "@example/llc-a",
+            "LLC_a",
+            FontRequirement::None,
+        )];
+        let path = dir.join(GAME_LANG_CONFIG);
+
+        fs::write(&path, "\u{feff}{\"volume\":1,\"lang\":\"custom\"}").unwrap();
+        select_pack(&dir, &dir, &packs[0], &packs).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            r#"{"volume":1,"lang":"LLC_a"}"#
+        );
+
+        // nothing was selected before, the fields after the selection keep their place
+        fs::write(&path, r#"{"volume":1,"lang":"LLC_a","music":2,"voice":3}"#).unwrap();
+        fs::remove_file(dir.join(PREVIOUS_LANG_FILE)).unwrap();
+        restore_previous(&dir, &dir, &packs).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            r#"{"volume":1,"music":2,"voice":3}"#
+        );
+
+        // left alone rather than overwritten
+        fs::write(&path, "[]").unwrap();
+        assert!(select_pack(&dir, &dir, &packs[0], &packs).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+    }
+}
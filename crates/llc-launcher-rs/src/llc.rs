@@ -1,24 +1,85 @@
-use bytes::Bytes;
-use eyre::{Context, ContextCompat};
+use crate::{
+    archive, game_lang,
+    manifest::{FileEntry, Manifest, manifest_key},
+    progress::{ProgressSender, Stage},
+};
+use directories::ProjectDirs;
+use eyre::{Context, ContextCompat, bail};
 use flate2::read::GzDecoder;
 use llc_rs::{
-    LLCConfig, get_limbus_company_install_path, launch_limbus_company,
-    npm::{DistInfo, NpmClient},
-    utils::OptionExt,
+    FontRequirement, LLCConfig, LanguagePack, get_limbus_company_install_path,
+    launch_limbus_company,
+    npm::{DistInfo, NpmClient, NpmError, Packument, Release, VersionSpec},
+    utils::{CancellationToken, OptionExt},
 };
+use semver::Version;
 use serde_json::Value;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Font installed for [`FontRequirement::BundledChinese`], in the folder of the pack.
+pub const INSTALLED_FONT: &str = "Font/Context/ChineseFont.ttf";
+
+/// Where the tarball of a language pack contains the game's `Lang` directory.
+const RELEASE_PREFIX: &str = "package/LimbusCompany_Data/Lang";
+
+/// Where a language pack lives in the game's `Lang` directory.
+#[derive(Debug, Clone)]
+pub struct PackPaths {
+    /// The installed release.
+    pub installed: PathBuf,
+    /// A release being extracted, next to `installed` so that it can be renamed into place.
+    pub staging: PathBuf,
+    /// The previously installed release.
+    pub backup: PathBuf,
+}
+
+impl PackPaths {
+    pub fn new(lang_dir: &Path, pack: &LanguagePack) -> Self {
+        PackPaths {
+            installed: lang_dir.join(pack.lang_dir()),
+            staging: lang_dir.join(format!("{}.staging", pack.lang_dir())),
+            backup: lang_dir.join(format!("{}.backup", pack.lang_dir())),
+        }
+    }
+}
 
-const PKG_NAME: &str = "@lightsing/llc-zh-cn";
+/// Where the tarball of `pack` contains its files.
+fn release_prefix(pack: &LanguagePack) -> PathBuf {
+    Path::new(RELEASE_PREFIX).join(pack.lang_dir())
+}
 
-pub async fn run(llc_config: LLCConfig) -> eyre::Result<()> {
-    install_or_update_llc(llc_config)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    /// Skip the update check and use the installed version.
+    pub offline: bool,
+    /// Check the installed files and repair damaged ones before launching.
+    pub verify: bool,
+    /// Pin the version published before the installed one.
+    pub rollback: bool,
+}
+
+/// Install or update the selected language pack and launch the game.
+///
+/// A rollback pins the version in `llc_config`.
+pub async fn run(
+    dirs: &ProjectDirs,
+    llc_config: &mut LLCConfig,
+    options: RunOptions,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    install_or_update_llc(dirs, llc_config, options, progress.clone())
         .await
         .inspect_err(|e| error!("Failed to install or update LLC: {e}"))
         .context("无法安装或更新 LLC")?;
 
     info!("LLC installation or update completed successfully.");
 
+    progress.stage(Stage::Launch);
     launch_limbus_company()
         .inspect_err(|e| error!("cannot start Limbus Company: {e}"))
         .context("无法启动 Limbus Company")?;
@@ -57,20 +118,45 @@ async fn copy_self_to_launcher() -> eyre::Result<()> {
     Ok(())
 }
 
-async fn install_or_update_llc(llc_config: LLCConfig) -> eyre::Result<()> {
+async fn install_or_update_llc(
+    dirs: &ProjectDirs,
+    llc_config: &mut LLCConfig,
+    options: RunOptions,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    progress.stage(Stage::CheckUpdate);
+
+    let pack = llc_config
+        .language_pack()
+        .cloned()
+        .context("配置中选择的语言包不存在")?;
+    info!("Language pack: {} in {}", pack.package(), pack.lang_dir());
+
     let game_root = get_limbus_company_install_path()
         .inspect_err(|e| error!("failed to get Limbus Company install path: {e}"))
         .context("无法获取 Limbus Company 安装路径")?;
     info!("Limbus Company install path: {}", game_root.display());
 
-    tokio::fs::create_dir_all(game_root.join("LimbusCompany_Data").join("Lang"))
+    let lang_dir = lang_dir(&game_root);
+    tokio::fs::create_dir_all(&lang_dir)
         .await
         .inspect_err(|e| error!("Failed to create LLC directory: {e}"))
         .context("无法创建语言目录")?;
+    let paths = PackPaths::new(&lang_dir, &pack);
+    recover_interrupted_update(&paths)
+        .inspect_err(|e| warn!("Failed to recover from an interrupted update: {e}"))
+        .ok();
 
-    let font_installer = tokio::spawn(install_font_if_needed(game_root.clone()));
+    if options.rollback {
+        let previous = previous_version(dirs, llc_config, &pack, &paths, options.offline)
+            .await
+            .inspect_err(|e| error!("Failed to find the previous LLC version: {e}"))
+            .context("无法确定上一个 LLC 版本")?;
+        info!("Rolling back to LLC {previous}, pinned until cleared with --unpin.");
+        llc_config.set_pinned_version(Some(previous));
+    }
 
-    let installed_tag = match get_version_installed(&game_root) {
+    let installed_tag = match read_version(&paths.installed) {
         Ok(Some(version)) => version,
         Ok(None) => {
             info!("No version installed, proceeding with installation.");
@@ -82,59 +168,239 @@ async fn install_or_update_llc(llc_config: LLCConfig) -> eyre::Result<()> {
         }
     };
 
-    let latest_version = NpmClient::new(llc_config.npm_registries())
-        .get_lastest_version(PKG_NAME)
+    // a pinned version, e.g. from a rollback, is installed from the caches
+    if options.offline && llc_config.pinned_version().is_none() {
+        warn!("Offline mode is enabled, skipping the LLC update check.");
+        progress.offline();
+    } else {
+        update_llc(
+            dirs.cache_dir(),
+            llc_config.clone(),
+            &pack,
+            &paths,
+            &installed_tag,
+            options.offline,
+            progress.clone(),
+        )
+        .await?;
+    }
+
+    if options.verify {
+        verify_and_repair(
+            dirs,
+            llc_config.clone(),
+            &pack,
+            &paths,
+            options.offline,
+            progress,
+        )
         .await
-        .inspect_err(|e| error!("Failed to get latest LLC version: {e}"))
-        .context("无法获取最新 LLC 版本")?;
-    let tag = latest_version
-        .github_tag
-        .context("无法获取最新 LLC 版本的发布标签")?;
-    info!("Latest version available: {tag}");
+        .inspect_err(|e| error!("Failed to verify LLC files: {e}"))
+        .context("无法校验并修复 LLC 文件")?;
+    }
+
+    // after the update, as the whole pack directory may have been replaced
+    if pack.font() == FontRequirement::BundledChinese {
+        install_font_if_needed(&paths.installed)
+            .await
+            .inspect_err(|e| error!("Failed to install font: {e}"))
+            .context("无法安装字体")?;
+    }
+
+    // the pack can still be selected in the game by hand, so launch anyway
+    if paths.installed.exists()
+        && let Err(e) = game_lang::select_pack(
+            &lang_dir,
+            dirs.data_dir(),
+            &pack,
+            llc_config.language_packs(),
+        )
+    {
+        warn!("Failed to select the language pack in the game: {e}");
+    }
+
+    Ok(())
+}
+
+/// Update LLC to the pinned or else the latest version, unless `installed_tag` is that version
+/// already.
+///
+/// Falls back to the installed version if no registry can be reached, or when `offline` the
+/// release isn't cached. The installed version is only replaced once the new one is fully
+/// extracted.
+async fn update_llc(
+    cache_dir: &Path,
+    llc_config: LLCConfig,
+    pack: &LanguagePack,
+    paths: &PackPaths,
+    installed_tag: &str,
+    offline: bool,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    let spec = match llc_config.pinned_version() {
+        Some(version) => {
+            info!("LLC is pinned to version {version}.");
+            VersionSpec::exact(version)
+        }
+        None => VersionSpec::latest(),
+    };
+    let client = NpmClient::from_config(&llc_config)
+        .inspect_err(|e| error!("Failed to create npm client: {e}"))
+        .context("无法创建网络客户端")?
+        .with_cache_dir(cache_dir)
+        .cache_only(offline);
+    let release = match client.resolve(pack.package(), &spec).await {
+        Ok(release) => release,
+        // an installed translation is good enough to play
+        Err(e @ (NpmError::Http(_) | NpmError::NotCached(_))) if !installed_tag.is_empty() => {
+            warn!("Cannot get LLC {spec}, launching with LLC {installed_tag} offline: {e}");
+            progress.offline();
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("Failed to get LLC version {spec}: {e}"))
+                .context("无法获取 LLC 版本信息");
+        }
+    };
+    let tag = release.github_tag.context("无法获取 LLC 版本的发布标签")?;
+    info!("Version to install: {tag}");
+    if let Some(reason) = &release.deprecated {
+        warn!("LLC {tag} ({}) is deprecated: {reason}", release.version);
+    }
+    warn_if_installed_deprecated(&client, pack, paths).await;
 
     if installed_tag == tag {
         info!("LLC is already up to date (version {}).", installed_tag);
         return Ok(());
     }
 
-    let cleaner = tokio::spawn(cleanup_installed_llc(game_root.clone()));
-    let downloader = tokio::spawn(download_release(llc_config, latest_version.dist));
-
     info!("Updating LLC from version {installed_tag} to {tag}.",);
+    progress.stage(Stage::Download);
+    let tarball = match download_release(
+        llc_config.clone(),
+        release.dist,
+        cache_dir.to_path_buf(),
+        offline,
+        progress.clone(),
+    )
+    .await
+    {
+        Ok(tarball) => tarball,
+        Err(e @ (NpmError::Http(_) | NpmError::NotCached(_))) if !installed_tag.is_empty() => {
+            warn!("Cannot download LLC {tag}, launching with LLC {installed_tag} offline: {e}");
+            progress.offline();
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("Failed to download LLC: {e}"))
+                .context("无法下载 LLC 文件");
+        }
+    };
 
-    cleaner
-        .await
-        .inspect_err(|e| error!("Failed to clean up installed files: {e}"))
-        .context("无法清理已安装的文件")?;
+    progress.stage(Stage::Extract);
+    stage_release(
+        &tarball,
+        &release_prefix(pack),
+        paths,
+        &tag,
+        &release.version,
+        progress,
+    )
+    .await
+    .inspect_err(|e| error!("Failed to extract LLC update: {e}"))
+    .context("无法解压 LLC 更新")?;
+    swap_in_release(paths)
+        .inspect_err(|e| error!("Failed to apply LLC update: {e}"))
+        .context("无法应用 LLC 更新")?;
+    tokio::fs::remove_file(&tarball).await.ok();
+    Ok(())
+}
 
-    let tarball = downloader
-        .await
-        .map_err(|e| e.into())
-        .and_then(|res| res)
-        .inspect_err(|e| error!("Failed to download LLC: {e}"))
-        .context("无法下载 LLC 文件")?;
-    extract_apply_release(tarball, game_root.clone())
-        .await
-        .inspect_err(|e| error!("Failed to extract and apply LLC update: {e}"))
-        .context("无法解压并应用 LLC 更新")?;
+/// Warn if the installed version of `pack` has been deprecated, e.g. for broken dialogue.
+async fn warn_if_installed_deprecated(
+    client: &NpmClient<'_>,
+    pack: &LanguagePack,
+    paths: &PackPaths,
+) {
+    let Ok(Some(manifest)) = Manifest::load(&paths.installed) else {
+        return;
+    };
+    // usually revalidated from the metadata cache, the version was just resolved from it
+    let packument = match client.get_abbreviated_packument(pack.package()).await {
+        Ok(packument) => packument,
+        Err(e) => {
+            warn!("Failed to check if the installed LLC is deprecated: {e}");
+            return;
+        }
+    };
+    if let Some(reason) = packument
+        .version(&manifest.version)
+        .and_then(|meta| meta.deprecated.as_deref())
+    {
+        warn!(
+            "Installed LLC {} ({}) is deprecated: {reason}",
+            manifest.tag, manifest.version
+        );
+    }
+}
 
-    font_installer
-        .await
-        .map_err(|e| e.into())
-        .and_then(|res| res)
-        .inspect_err(|e| error!("Failed to install font: {e}"))
-        .context("无法安装字体")?;
+/// The newest version of `pack` published before the installed one, from the cached package
+/// document when `offline`.
+async fn previous_version(
+    dirs: &ProjectDirs,
+    llc_config: &LLCConfig,
+    pack: &LanguagePack,
+    paths: &PackPaths,
+    offline: bool,
+) -> eyre::Result<Version> {
+    let client = NpmClient::from_config(llc_config)?
+        .with_cache_dir(dirs.cache_dir())
+        .cache_only(offline);
+    let packument = match client.get_packument(pack.package()).await {
+        // usually only the abbreviated document was fetched before
+        Err(NpmError::NotCached(_)) => client.get_abbreviated_packument(pack.package()).await?,
+        packument => packument?,
+    };
+    let installed = match Manifest::load(&paths.installed)? {
+        Some(manifest) => manifest.version,
+        // installed before manifests were written, only the tag is known
+        None => {
+            let tag = read_version(&paths.installed)?.context("no version installed")?;
+            installed_release(&packument, &tag)
+                .with_context(|| format!("no version tagged {tag}"))?
+        }
+    };
+    previous_release(&packument.releases(), &installed)
+        .with_context(|| format!("no version before {installed}"))
+}
 
-    Ok(())
+/// The version of `packument` published with the GitHub release `tag`.
+fn installed_release(packument: &Packument, tag: &str) -> Option<Version> {
+    packument
+        .versions
+        .iter()
+        .find(|(_, meta)| meta.github_tag.as_deref() == Some(tag))
+        .map(|(version, _)| version.clone())
+}
+
+/// The newest of `releases` (newest first) before `installed`, skipping pre-releases.
+fn previous_release(releases: &[Release], installed: &Version) -> Option<Version> {
+    releases
+        .iter()
+        .map(|release| &release.version)
+        .find(|version| *version < installed && version.pre.is_empty())
+        .cloned()
+}
+
+pub fn lang_dir(game_root: &Path) -> PathBuf {
+    game_root.join("LimbusCompany_Data").join("Lang")
 }
 
-fn get_version_installed(game_root: &Path) -> eyre::Result<Option<String>> {
-    let version_file = game_root
-        .join("LimbusCompany_Data")
-        .join("Lang")
-        .join("LLC_zh-CN")
-        .join("Info")
-        .join("version.json");
+/// Version of the release in `llc_dir`.
+fn read_version(llc_dir: &Path) -> eyre::Result<Option<String>> {
+    let version_file = llc_dir.join("Info").join("version.json");
     if !version_file.exists() {
         info!("Version file does not exist at {}", version_file.display());
         return Ok(None);
@@ -155,60 +421,127 @@ fn get_version_installed(game_root: &Path) -> eyre::Result<Option<String>> {
     }
 }
 
-async fn cleanup_installed_llc(game_root: PathBuf) {
-    let llc_dir = game_root
-        .join("LimbusCompany_Data")
-        .join("Lang")
-        .join("LLC_zh-CN");
-    if !llc_dir.exists() {
-        return;
-    }
-    let Ok(mut read_dir) = tokio::fs::read_dir(llc_dir).await else {
-        warn!("Failed to read LLC directory for cleanup");
-        return;
+/// Extract the release into a fresh staging directory, check that it is complete and write its
+/// manifest.
+async fn stage_release(
+    tarball: &Path,
+    prefix: &Path,
+    paths: &PackPaths,
+    tag: &str,
+    version: &Version,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    let staging = &paths.staging;
+    let stage = async {
+        if staging.exists() {
+            tokio::fs::remove_dir_all(&staging).await?;
+        }
+        tokio::fs::create_dir_all(&staging).await?;
+        let files = extract_release(tarball, prefix, staging, progress).await?;
+        match read_version(staging)? {
+            Some(staged) if staged != tag => {
+                warn!("Staged LLC version {staged} doesn't match the release tag {tag}")
+            }
+            Some(_) => {}
+            None => bail!("no version file in the release"),
+        }
+        Manifest {
+            tag: tag.to_string(),
+            version: version.clone(),
+            files,
+        }
+        .save(staging)
     };
-    while let Ok(Some(entry)) = read_dir.next_entry().await {
-        if entry.file_name() == "Font" {
-            continue; // Skip the Font directory
+    stage.await.inspect_err(|_| {
+        fs::remove_dir_all(staging).ok();
+    })
+}
+
+/// Move the staged release into place, keeping the installed one as backup.
+///
+/// Both steps are renames within the `Lang` directory, the installed release is restored if the
+/// staged one can't be moved into place.
+fn swap_in_release(paths: &PackPaths) -> eyre::Result<()> {
+    let PackPaths {
+        installed: live,
+        staging,
+        backup,
+    } = paths;
+
+    let installed = live.exists();
+    if installed {
+        // fonts are installed separately and not part of every release
+        copy_missing(&live.join("Font"), &staging.join("Font"))?;
+        if backup.exists() {
+            fs::remove_dir_all(backup)?;
+        }
+        fs::rename(live, backup)?;
+    }
+    if let Err(e) = fs::rename(staging, live) {
+        if installed {
+            fs::rename(backup, live)
+                .inspect_err(|e| error!("Failed to restore the previous LLC: {e}"))?;
+            info!("Restored the previous LLC from {}", backup.display());
         }
-        let path = entry.path();
-        if path.is_dir() {
-            tokio::fs::remove_dir_all(path).await.ok();
-        } else if path.is_file() {
-            tokio::fs::remove_file(path).await.ok();
-        } else {
-            warn!("Found suspicious path: {:?}", path.display());
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Put the backup back in place if an update was interrupted between the two renames of
+/// [`swap_in_release`], and drop leftovers of an interrupted extraction.
+fn recover_interrupted_update(paths: &PackPaths) -> io::Result<()> {
+    if !paths.installed.exists() && paths.backup.exists() {
+        warn!(
+            "{} is missing, restoring {}",
+            paths.installed.display(),
+            paths.backup.display()
+        );
+        fs::rename(&paths.backup, &paths.installed)?;
+    }
+    match fs::remove_dir_all(&paths.staging) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Copy the files below `src` which don't exist below `dst`.
+fn copy_missing(src: &Path, dst: &Path) -> io::Result<()> {
+    let read_dir = match fs::read_dir(src) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    fs::create_dir_all(dst)?;
+    for entry in read_dir {
+        let entry = entry?;
+        let dst = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_missing(&entry.path(), &dst)?;
+        } else if !dst.exists() {
+            fs::copy(entry.path(), &dst)?;
         }
     }
+    Ok(())
 }
 
-async fn install_font_if_needed(game_root: PathBuf) -> eyre::Result<()> {
+async fn install_font_if_needed(llc_dir: &Path) -> eyre::Result<()> {
     static FONT_FILE: &[u8] = include_bytes!("../../../assets/SarasaGothicSC-Bold.ttf");
 
-    let font_dir = game_root
-        .join("LimbusCompany_Data")
-        .join("Lang")
-        .join("LLC_zh-CN")
-        .join("Font");
+    let font_dir = llc_dir.join("Font");
 
     tokio::fs::create_dir_all(&font_dir).await?;
     tokio::fs::create_dir_all(font_dir.join("Context")).await?;
     tokio::fs::create_dir_all(font_dir.join("Title")).await?;
 
-    let font_file = game_root
-        .join("LimbusCompany_Data")
-        .join("Lang")
-        .join("LLC_zh-CN")
-        .join("Font")
-        .join("Context")
-        .join("ChineseFont.ttf");
+    let font_file = llc_dir.join(INSTALLED_FONT);
 
     let mut font_valid = false;
 
-    if let Ok(font_data) = tokio::fs::read(&font_file).await {
-        if ttf_parser::Face::parse(&font_data, 0).is_ok() {
-            font_valid = true;
-        }
+    if let Ok(font_data) = tokio::fs::read(&font_file).await
+        && ttf_parser::Face::parse(&font_data, 0).is_ok()
+    {
+        font_valid = true;
     }
 
     if font_valid {
@@ -227,55 +560,450 @@ async fn install_font_if_needed(game_root: PathBuf) -> eyre::Result<()> {
     Ok(())
 }
 
-async fn download_release(llc_config: LLCConfig, dist: DistInfo) -> eyre::Result<Bytes> {
-    let client = NpmClient::new(llc_config.npm_registries());
-    let buffer = client.download_dist(dist).await?;
-    Ok(buffer)
+async fn download_release(
+    llc_config: LLCConfig,
+    dist: DistInfo,
+    download_dir: PathBuf,
+    offline: bool,
+    progress: ProgressSender,
+) -> Result<PathBuf, NpmError> {
+    let client = NpmClient::from_config(&llc_config)?
+        .with_cache_dir(&download_dir)
+        .cache_only(offline);
+    let tarball = client
+        .download_dist_with_progress(&dist, &download_dir, progress, &CancellationToken::new())
+        .await?;
+    Ok(tarball)
 }
 
-async fn extract_apply_release(tarball: Bytes, game_root: PathBuf) -> eyre::Result<()> {
-    let tar = GzDecoder::new(tarball.as_ref());
-    let mut archive = tar::Archive::new(tar);
+/// Extract the files below `prefix` in the release into `dst_dir`, returning the extracted files
+/// for the manifest.
+///
+/// Fails if the release contains unsafe entries, anywhere in the archive.
+async fn extract_release(
+    tarball: &Path,
+    prefix: &Path,
+    dst_dir: &Path,
+    progress: ProgressSender,
+) -> eyre::Result<BTreeMap<String, FileEntry>> {
+    // count entries up front so the splash can show a determinate bar
+    let mut total = 0;
+    for file in tar::Archive::new(GzDecoder::new(std::fs::File::open(tarball)?)).entries()? {
+        if file?.path()?.starts_with(prefix) {
+            total += 1;
+        }
+    }
+    progress.extract(0, total);
 
-    let dst_dir = game_root
-        .join("LimbusCompany_Data")
-        .join("Lang")
-        .join("LLC_zh-CN");
+    let tar = GzDecoder::new(std::fs::File::open(tarball)?);
+    let mut archive = tar::Archive::new(tar);
 
+    let mut extracted = 0;
+    let mut rejected = vec![];
+    let mut files = BTreeMap::new();
     for file in archive.entries()? {
         let mut file = file?;
-        let path = file.path()?.to_path_buf();
-        let Ok(path) = path.strip_prefix("package/LimbusCompany_Data/Lang/LLC_zh-CN") else {
+        let path = match archive::check_entry(&file) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Rejected release entry {e}");
+                rejected.push(e);
+                continue;
+            }
+        };
+        let Ok(path) = path.strip_prefix(prefix) else {
             continue;
         };
-        let dest_path = dst_dir.join(path);
-        if let Some(parent) = dest_path.parent()
-            && !parent.exists()
-        {
-            tokio::fs::create_dir_all(parent).await?;
+        let dest = dst_dir.join(path);
+        archive::unpack(&mut file, &dest)?;
+        if file.header().entry_type().is_file() {
+            files.insert(manifest_key(path), FileEntry::read(&dest)?);
         }
-        file.unpack(&dest_path)?;
-        let file_time = filetime::FileTime::now();
-        filetime::set_file_atime(&dest_path, file_time)?;
-        filetime::set_file_mtime(&dest_path, file_time)?;
+
+        extracted += 1;
+        progress.extract(extracted, total);
     }
 
+    archive::ensure_none_rejected(&rejected)?;
+    Ok(files)
+}
+
+/// Check the installed files against the manifest of their release, restoring missing and
+/// modified files from the release tarball and removing extra ones.
+///
+/// An installation without manifest is reinstalled.
+async fn verify_and_repair(
+    dirs: &ProjectDirs,
+    llc_config: LLCConfig,
+    pack: &LanguagePack,
+    paths: &PackPaths,
+    offline: bool,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    progress.stage(Stage::Verify);
+    let llc_dir = &paths.installed;
+    let Some(manifest) = Manifest::load(llc_dir).context("无法读取 LLC 文件清单")? else {
+        warn!("No manifest for the installed LLC, reinstalling.");
+        return Box::pin(update_llc(
+            dirs.cache_dir(),
+            llc_config,
+            pack,
+            paths,
+            "",
+            offline,
+            progress,
+        ))
+        .await;
+    };
+
+    let total = manifest.files.len() as u64;
+    let damage = manifest
+        .verify(llc_dir, |checked| progress.extract(checked, total))
+        .context("无法校验 LLC 文件")?;
+    if damage.is_empty() {
+        info!("All {total} files of LLC {} are intact.", manifest.tag);
+        return Ok(());
+    }
+    warn!(
+        "LLC {} is damaged, missing: {:?}, modified: {:?}, extra: {:?}",
+        manifest.tag, damage.missing, damage.modified, damage.extra
+    );
+
+    for key in &damage.extra {
+        fs::remove_file(llc_dir.join(key))
+            .inspect_err(|e| warn!("Failed to remove extra file {key}: {e}"))
+            .ok();
+    }
+    let restore = damage.to_restore();
+    if restore.is_empty() {
+        return Ok(());
+    }
+
+    // the tarball is usually still in the tarball cache
+    let download = async {
+        let release = NpmClient::from_config(&llc_config)?
+            .with_cache_dir(dirs.cache_dir())
+            .cache_only(offline)
+            .get_version_manifest(pack.package(), &manifest.version)
+            .await?;
+        download_release(
+            llc_config.clone(),
+            release.dist,
+            dirs.cache_dir().to_path_buf(),
+            offline,
+            progress.clone(),
+        )
+        .await
+    };
+    let tarball = match download.await {
+        Ok(tarball) => tarball,
+        // the intact files are still good enough to play
+        Err(e @ (NpmError::Http(_) | NpmError::NotCached(_))) => {
+            warn!(
+                "Cannot download LLC {}, launching without repairing it: {e}",
+                manifest.tag
+            );
+            progress.offline();
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("Failed to download LLC: {e}"))
+                .context("无法下载 LLC 文件");
+        }
+    };
+    restore_files(
+        &tarball,
+        &release_prefix(pack),
+        llc_dir,
+        &manifest,
+        &restore,
+    )?;
+    tokio::fs::remove_file(&tarball).await.ok();
+    info!("Repaired {} files of LLC {}.", restore.len(), manifest.tag);
+    Ok(())
+}
+
+/// Extract the `restore` files below `prefix` in the release into `llc_dir`, checking them
+/// against the manifest.
+fn restore_files(
+    tarball: &Path,
+    prefix: &Path,
+    llc_dir: &Path,
+    manifest: &Manifest,
+    restore: &BTreeSet<&str>,
+) -> eyre::Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(std::fs::File::open(tarball)?));
+    let mut restored = 0;
+    for file in archive.entries()? {
+        let mut file = file?;
+        // unsafe entries were refused on install already
+        let Ok(path) = archive::check_entry(&file) else {
+            continue;
+        };
+        let Ok(path) = path.strip_prefix(prefix) else {
+            continue;
+        };
+        let key = manifest_key(path);
+        if !restore.contains(key.as_str()) {
+            continue;
+        }
+        let dest = llc_dir.join(path);
+        archive::unpack(&mut file, &dest)?;
+        if manifest.files.get(&key) != Some(&FileEntry::read(&dest)?) {
+            bail!("{key} in the release doesn't match the manifest");
+        }
+        restored += 1;
+    }
+    if restored != restore.len() {
+        bail!(
+            "{} damaged files are not in the release",
+            restore.len() - restored
+        );
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use llc_rs::test_support::{MockRegistry, Package, TempDir, mock_config, npm_tarball};
     use tokio::test;
 
+    fn zh_cn() -> LanguagePack {
+        LLCConfig::default().language_pack().unwrap().clone()
+    }
+
     #[test]
     async fn test_download_extract_release() {
-        let game_root = get_limbus_company_install_path().unwrap();
-        let llc_config = LLCConfig::default();
-        let npm_client = NpmClient::new(llc_config.npm_registries());
-        let dist = npm_client.get_lastest_version(PKG_NAME).await.unwrap().dist;
+        // any pack with the same layout works
+        let pack = LanguagePack::new("@example/llc-zh-tw", "LLC_zh-TW", FontRequirement::None);
+        let registry = MockRegistry::start().await;
+        registry.publish(
+            Package::new(
+                pack.package(),
+                "1.0.0",
+                npm_tarball(&[
+                    ("LimbusCompany_Data/Lang/LLC_zh-TW/Font/font.ttf", b"font"),
+                    (
+                        "LimbusCompany_Data/Lang/LLC_zh-TW/Info/version.json",
+                        br#"{"version":"v1.0.0"}"#,
+                    ),
+                    ("LimbusCompany_Data/Lang/LLC_zh-CN/other.json", b"{}"),
+                    ("package.json", b"{}"),
+                ]),
+            )
+            .github_tag("v1.0.0"),
+        );
+        let llc_config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&llc_config).unwrap();
+        let dist = npm_client
+            .get_lastest_version(pack.package())
+            .await
+            .unwrap()
+            .dist;
+        let download_dir = TempDir::new("llc-launcher-download");
+        let game_root = TempDir::new("llc-launcher-game");
+        let lang_dir = lang_dir(&game_root);
+        let paths = PackPaths::new(&lang_dir, &pack);
+        let prefix = release_prefix(&pack);
+        let v1 = Version::new(1, 0, 0);
+
+        let (progress, _rx) = crate::progress::channel();
+        let tarball = download_release(
+            llc_config,
+            dist,
+            download_dir.to_path_buf(),
+            false,
+            progress.clone(),
+        )
+        .await
+        .unwrap();
+        stage_release(&tarball, &prefix, &paths, "v1.0.0", &v1, progress)
+            .await
+            .unwrap();
+        swap_in_release(&paths).unwrap();
+
+        let llc_dir = &paths.installed;
+        assert_eq!(llc_dir, &lang_dir.join("LLC_zh-TW"));
+        assert_eq!(
+            std::fs::read(llc_dir.join("Font/font.ttf")).unwrap(),
+            b"font"
+        );
+        assert_eq!(read_version(llc_dir).unwrap().as_deref(), Some("v1.0.0"));
+        assert!(!game_root.join("package.json").exists());
+        assert!(!lang_dir.join("LLC_zh-CN").exists());
+        assert!(!paths.staging.exists());
+
+        // damaged files are found with the manifest and restored from the tarball
+        let manifest = Manifest::load(llc_dir).unwrap().unwrap();
+        assert_eq!(manifest.version, v1);
+        assert_eq!(manifest.files.len(), 2);
+        assert!(manifest.verify(llc_dir, |_| {}).unwrap().is_empty());
+        std::fs::remove_file(llc_dir.join("Font/font.ttf")).unwrap();
+        std::fs::write(llc_dir.join("Info/version.json"), "{}").unwrap();
+        let damage = manifest.verify(llc_dir, |_| {}).unwrap();
+        restore_files(&tarball, &prefix, llc_dir, &manifest, &damage.to_restore()).unwrap();
+        assert!(manifest.verify(llc_dir, |_| {}).unwrap().is_empty());
+    }
+
+    #[test]
+    async fn test_update_without_tarball() {
+        let registry = MockRegistry::start().await;
+        let pack = zh_cn();
+        let package = Package::new(
+            pack.package(),
+            "2.0.0",
+            npm_tarball(&[(
+                "LimbusCompany_Data/Lang/LLC_zh-CN/Info/version.json",
+                br#"{"version":"v2"}"#,
+            )]),
+        )
+        .github_tag("v2");
+        registry.publish(package.clone());
+        registry.fail_tarballs(Some(503));
+        let config = mock_config(&[&registry]);
+        let game_root = TempDir::new("llc-launcher-update-without-tarball");
+        let cache_dir = game_root.join("cache");
+        let paths = PackPaths::new(&lang_dir(&game_root), &pack);
+        let (progress, _rx) = crate::progress::channel();
+
+        // nothing installed to launch with
+        assert!(
+            update_llc(
+                &cache_dir,
+                config.clone(),
+                &pack,
+                &paths,
+                "",
+                false,
+                progress.clone(),
+            )
+            .await
+            .is_err()
+        );
+
+        // the installed version is kept
+        std::fs::create_dir_all(paths.installed.join("Info")).unwrap();
+        std::fs::write(
+            paths.installed.join("Info/version.json"),
+            r#"{"version":"v1"}"#,
+        )
+        .unwrap();
+        update_llc(&cache_dir, config, &pack, &paths, "v1", false, progress)
+            .await
+            .unwrap();
+        assert!(
+            registry
+                .requests()
+                .iter()
+                .any(|request| request.path == registry.tarball_url(&package).path())
+        );
+        assert_eq!(
+            read_version(&paths.installed).unwrap().as_deref(),
+            Some("v1")
+        );
+    }
+
+    #[test]
+    async fn test_staged_update() {
+        let pack = zh_cn();
+        let game_root = TempDir::new("llc-launcher-staged-update");
+        let lang_dir = lang_dir(&game_root);
+        let paths = PackPaths::new(&lang_dir, &pack);
+        let prefix = release_prefix(&pack);
+        let v2 = Version::new(2, 0, 0);
+        let write = |path: &str, content: &str| {
+            let path = lang_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write("LLC_zh-CN/Info/version.json", r#"{"version":"v1"}"#);
+        write("LLC_zh-CN/Font/Context/ChineseFont.ttf", "font");
+        write("LLC_zh-CN/old.json", "old");
+
+        // a broken release leaves the installed version alone
+        let (progress, _rx) = crate::progress::channel();
+        let broken = game_root.join("broken.tgz");
+        std::fs::write(
+            &broken,
+            npm_tarball(&[("LimbusCompany_Data/Lang/LLC_zh-CN/new.json", b"new")]),
+        )
+        .unwrap();
+        assert!(
+            stage_release(&broken, &prefix, &paths, "v2", &v2, progress.clone())
+                .await
+                .is_err()
+        );
+        assert!(!paths.staging.exists());
+        assert_eq!(
+            read_version(&paths.installed).unwrap().as_deref(),
+            Some("v1")
+        );
+
+        let release = game_root.join("release.tgz");
+        std::fs::write(
+            &release,
+            npm_tarball(&[
+                ("LimbusCompany_Data/Lang/LLC_zh-CN/new.json", b"new"),
+                (
+                    "LimbusCompany_Data/Lang/LLC_zh-CN/Info/version.json",
+                    br#"{"version":"v2"}"#,
+                ),
+            ]),
+        )
+        .unwrap();
+        stage_release(&release, &prefix, &paths, "v2", &v2, progress)
+            .await
+            .unwrap();
+        swap_in_release(&paths).unwrap();
+        let llc_dir = &paths.installed;
+        assert!(llc_dir.join("new.json").exists());
+        assert!(!llc_dir.join("old.json").exists());
+        assert!(llc_dir.join("Font/Context/ChineseFont.ttf").exists());
+        assert!(paths.backup.join("old.json").exists());
+
+        // an update interrupted between the renames is rolled back on the next start
+        std::fs::remove_dir_all(llc_dir).unwrap();
+        recover_interrupted_update(&paths).unwrap();
+        assert_eq!(read_version(llc_dir).unwrap().as_deref(), Some("v1"));
+    }
+
+    #[test]
+    async fn test_previous_release() {
+        let releases: Vec<_> = ["2.0.0", "2.0.0-beta.1", "1.1.0", "1.0.0"]
+            .into_iter()
+            .map(|version| Release {
+                version: version.parse().unwrap(),
+                published: None,
+                deprecated: None,
+                dist_tags: vec![],
+            })
+            .collect();
+        let previous = |installed: &str| previous_release(&releases, &installed.parse().unwrap());
+        assert_eq!(previous("2.0.0"), Some(Version::new(1, 1, 0)));
+        assert_eq!(previous("1.1.0"), Some(Version::new(1, 0, 0)));
+        assert_eq!(previous("1.0.0"), None);
+    }
+
+    #[test]
+    async fn test_installed_release() {
+        let registry = MockRegistry::start().await;
+        let pack = zh_cn();
+        for (version, tag) in [("1.0.0", "v1"), ("1.1.0", "v2")] {
+            registry.publish(Package::new(pack.package(), version, *b"").github_tag(tag));
+        }
+        let config = mock_config(&[&registry]);
+        let packument = NpmClient::from_config(&config)
+            .unwrap()
+            .get_packument(pack.package())
+            .await
+            .unwrap();
 
-        let tarball = download_release(llc_config, dist).await.unwrap();
-        extract_apply_release(tarball, game_root).await.unwrap();
+        assert_eq!(
+            installed_release(&packument, "v2"),
+            Some(Version::new(1, 1, 0))
+        );
+        assert_eq!(installed_release(&packument, "v3"), None);
     }
 }
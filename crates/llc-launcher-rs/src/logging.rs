@@ -9,8 +9,8 @@ use tracing_appender::{
 };
 use tracing_subscriber::{
     filter::filter_fn, fmt, fmt::writer::MakeWriterExt, layer::SubscriberExt,
+    util::SubscriberInitExt,
 };
-use tracing_subscriber::util::SubscriberInitExt;
 
 static NOISE_TARGETS: &[&str] = &[
     "async_io",
@@ -94,6 +94,9 @@ async fn init_inner(
                 .with_writer(non_blocking_stderr_appender.with_max_level(config.log_level())),
         );
 
+    // only the disabled SLS reporter below waits for shutdown
+    let _ = shutdown_rx;
+
     // #[cfg(not(debug_assertions))]
     // let sls_reporter = if config.telemetry() {
     //     let client = tracing_aliyun_sls::SlsClient::builder()
@@ -1,4 +1,3 @@
-#![feature(exit_status_error)]
 #![feature(formatting_options)]
 #![cfg_attr(
     all(target_os = "windows", not(debug_assertions)),
@@ -9,21 +8,27 @@
 #[macro_use]
 extern crate tracing;
 
-use crate::config::LauncherConfig;
+use crate::{config::LauncherConfig, progress::ProgressSender};
 use directories::ProjectDirs;
 use eframe::egui;
 use eyre::{Context, ContextCompat};
 use llc_rs::LLCConfig;
 use std::{fs, path::PathBuf, process::exit};
+use tokio::sync::oneshot;
 
 const ORGANIZATION: &str = "lightsing";
 const APP_NAME: &str = "llc-launcher-rs";
 
+mod archive;
 mod config;
+mod game_lang;
 mod llc;
 mod logging;
+mod manifest;
+mod progress;
 mod self_update;
 mod splash;
+mod uninstall;
 mod utils;
 
 #[cfg(test)]
@@ -43,6 +48,9 @@ fn main() {
     utils::install_eyre_hook().expect("Failed to install eyre");
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
+    let (progress_tx, progress_rx) = progress::channel();
+    // answered by the uninstall dialog, opened by `--uninstall` or from the error window
+    let (uninstall_tx, uninstall_rx) = oneshot::channel();
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -65,7 +73,15 @@ fn main() {
             eframe::run_native(
                 "Limbus Company Launcher",
                 options,
-                Box::new(|cc| Ok(Box::new(splash::SplashScreen::new(cc, false, shutdown_rx)))),
+                Box::new(|cc| {
+                    Ok(Box::new(splash::SplashScreen::new(
+                        cc,
+                        false,
+                        shutdown_rx,
+                        progress_rx,
+                        None,
+                    )))
+                }),
             )
             .expect("Failed to run the launcher splash screen");
             exit(-1);
@@ -80,7 +96,13 @@ fn main() {
             .enable_all()
             .build()
             .expect("Failed to create Tokio runtime")
-            .block_on(main_inner(init_res, shutdown_tx, shutdown_rx))
+            .block_on(main_inner(
+                init_res,
+                uninstall_rx,
+                shutdown_tx,
+                shutdown_rx,
+                progress_tx,
+            ))
     });
 
     eframe::run_native(
@@ -91,6 +113,8 @@ fn main() {
                 cc,
                 is_tool,
                 _shutdown_rx,
+                progress_rx,
+                Some(uninstall_tx),
             )))
         }),
     )
@@ -101,12 +125,14 @@ async fn main_inner(
     InitResources {
         dirs,
         launcher_config,
-        llc_config,
+        mut llc_config,
         self_path,
         is_tool,
     }: InitResources,
+    uninstall: oneshot::Receiver<uninstall::UninstallChoice>,
     shutdown_tx: tokio::sync::broadcast::Sender<()>,
     shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    progress: ProgressSender,
 ) {
     let _logging_guard = logging::init(&dirs, &launcher_config, shutdown_rx).await;
 
@@ -116,21 +142,38 @@ async fn main_inner(
         info!("Running as launcher, path: {}", self_path.display());
     }
 
-    if let Err(e) = {
-        if is_tool {
-            llc::run(llc_config.clone()).await
+    let offline = launcher_config.offline() || std::env::var_os(self_update::OFFLINE_ENV).is_some();
+    let options = llc::RunOptions {
+        offline,
+        verify: std::env::args_os().any(|arg| arg == "--verify"),
+        rollback: rollback_arg() == Some(None),
+    };
+    let result = if uninstall::requested() {
+        uninstall::run(&dirs, &self_path, &llc_config, uninstall, progress).await
+    } else {
+        let result = if is_tool {
+            llc::run(&dirs, &mut llc_config, options, progress.clone()).await
         } else {
-            self_update::run(&dirs, &self_path, &llc_config).await
+            self_update::run(&dirs, &self_path, &llc_config, offline, progress.clone()).await
+        };
+        // for migration, before an uninstall may remove the directories
+        config::save(&dirs, &launcher_config, &llc_config)
+            .inspect_err(|e| warn!("无法保存配置：{e}"))
+            .ok();
+
+        match result {
+            Err(e) => {
+                error!("{e:?}");
+                // the error window offers to uninstall until it is closed
+                uninstall::run(&dirs, &self_path, &llc_config, uninstall, progress).await
+            }
+            ok => ok,
         }
-    } {
+    };
+    if let Err(e) = result {
         error!("{e:?}");
     }
 
-    // for migration
-    config::save(&dirs, &launcher_config, &llc_config)
-        .inspect_err(|e| warn!("无法保存配置：{e}"))
-        .ok();
-
     shutdown_tx.send(()).ok();
     // if let Some(reporter) = logging_guard.sls_reporter {
     //     reporter.await.ok();
@@ -163,7 +206,24 @@ fn init() -> eyre::Result<InitResources> {
 
     let is_tool = self_path.starts_with(&cache_dir) || cfg!(debug_assertions);
 
-    let (launcher_config, llc_config) = config::load(&dirs);
+    let (launcher_config, mut llc_config) = config::load(&dirs);
+    // the selection is saved with the config once the launcher exits
+    if let Some(language) = arg_value("--language") {
+        llc_config
+            .select_language(&language)
+            .inspect_err(|e| error!("{e}"))
+            .context("未知的语言包")?;
+    }
+    if std::env::args_os().any(|arg| arg == "--unpin") {
+        llc_config.set_pinned_version(None);
+    }
+    if let Some(Some(version)) = rollback_arg() {
+        let version = version
+            .parse()
+            .inspect_err(|e| error!("invalid version {version}: {e}"))
+            .context("无效的 LLC 版本号")?;
+        llc_config.set_pinned_version(Some(version));
+    }
 
     Ok(InitResources {
         dirs,
@@ -173,3 +233,18 @@ fn init() -> eyre::Result<InitResources> {
         llc_config,
     })
 }
+
+/// Value following `flag` on the command line.
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args_os();
+    args.find(|arg| arg == flag)?;
+    args.next()
+        .map(|value| value.to_string_lossy().into_owned())
+}
+
+/// Version given to `--rollback`, `Some(None)` for a bare `--rollback` rolling back one version.
+fn rollback_arg() -> Option<Option<String>> {
+    std::env::args_os()
+        .any(|arg| arg == "--rollback")
+        .then(|| arg_value("--rollback").filter(|value| !value.starts_with("--")))
+}
@@ -0,0 +1,200 @@
+//! Manifest of the files of an installed release, so that damaged files can be found and
+//! repaired without reinstalling everything.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, ErrorKind, Read},
+    path::{Component, Path},
+};
+
+/// Manifest file inside the LLC directory.
+pub const MANIFEST_FILE: &str = ".llc-manifest.json";
+
+/// Fonts are installed separately and kept across releases, files there are never extra.
+const FONT_DIR: &str = "Font";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Release tag, as in `Info/version.json`.
+    pub tag: String,
+    /// npm version the files were extracted from.
+    pub version: Version,
+    /// Files by their `/` separated path in the LLC directory.
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    #[serde_as(as = "Hex")]
+    pub sha256: [u8; 32],
+}
+
+/// Differences between the manifest and the files on disk.
+#[derive(Debug, Default)]
+pub struct Damage {
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    pub extra: Vec<String>,
+}
+
+impl FileEntry {
+    /// Size and hash of the file at `path`.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            size += read as u64;
+        }
+        Ok(FileEntry {
+            size,
+            sha256: hasher.finalize().into(),
+        })
+    }
+}
+
+impl Manifest {
+    /// Read the manifest of the release in `llc_dir`, if it has one.
+    pub fn load(llc_dir: &Path) -> eyre::Result<Option<Self>> {
+        match fs::read(llc_dir.join(MANIFEST_FILE)) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, llc_dir: &Path) -> eyre::Result<()> {
+        fs::write(
+            llc_dir.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Compare the files in `llc_dir` with the manifest, calling `on_file` with the number of
+    /// files checked so far.
+    pub fn verify(&self, llc_dir: &Path, mut on_file: impl FnMut(u64)) -> io::Result<Damage> {
+        let mut damage = Damage::default();
+        for (checked, (key, expected)) in self.files.iter().enumerate() {
+            match FileEntry::read(&llc_dir.join(key)) {
+                Ok(actual) if actual == *expected => {}
+                Ok(_) => damage.modified.push(key.clone()),
+                Err(e) if e.kind() == ErrorKind::NotFound => damage.missing.push(key.clone()),
+                Err(e) => return Err(e),
+            }
+            on_file(checked as u64 + 1);
+        }
+
+        let mut present = BTreeSet::new();
+        collect_files(llc_dir, llc_dir, &mut present)?;
+        damage.extra = present
+            .into_iter()
+            .filter(|key| {
+                key != MANIFEST_FILE
+                    && !key.starts_with(&format!("{FONT_DIR}/"))
+                    && !self.files.contains_key(key)
+            })
+            .collect();
+        Ok(damage)
+    }
+}
+
+impl Damage {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.extra.is_empty()
+    }
+
+    /// Files to restore from the release.
+    pub fn to_restore(&self) -> BTreeSet<&str> {
+        self.missing
+            .iter()
+            .chain(&self.modified)
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// `/` separated form of a relative path, as used for manifest keys.
+pub fn manifest_key(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut BTreeSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.insert(manifest_key(relative));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llc_rs::test_support::TempDir;
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new("llc-launcher-manifest");
+        let write = |key: &str, content: &str| {
+            let path = dir.join(key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("Info/version.json", "{}");
+        write("a.json", "a");
+        write("b.json", "b");
+        write("Font/Context/ChineseFont.ttf", "font");
+
+        let mut manifest = Manifest {
+            tag: "v1".to_string(),
+            version: Version::new(1, 0, 0),
+            files: BTreeMap::new(),
+        };
+        for key in ["Info/version.json", "a.json", "b.json"] {
+            let entry = FileEntry::read(&dir.join(key)).unwrap();
+            manifest.files.insert(key.to_string(), entry);
+        }
+        manifest.save(&dir).unwrap();
+        let manifest = Manifest::load(&dir).unwrap().unwrap();
+        assert!(manifest.verify(&dir, |_| {}).unwrap().is_empty());
+
+        fs::remove_file(dir.join("a.json")).unwrap();
+        write("b.json", "evil");
+        write("Info/extra.json", "extra");
+        let mut checked = 0;
+        let damage = manifest.verify(&dir, |n| checked = n).unwrap();
+        assert_eq!(checked, 3);
+        assert_eq!(damage.missing, ["a.json"]);
+        assert_eq!(damage.modified, ["b.json"]);
+        assert_eq!(damage.extra, ["Info/extra.json"]);
+        assert_eq!(
+            damage.to_restore().into_iter().collect::<Vec<_>>(),
+            ["a.json", "b.json"]
+        );
+    }
+}
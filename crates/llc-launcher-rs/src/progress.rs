@@ -0,0 +1,216 @@
+//! Progress events published by the worker thread and rendered by the splash screen.
+
+use llc_rs::utils::DownloadObserver;
+use tokio::sync::broadcast;
+
+/// Coarse stage of the launcher run, in the order they usually happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    CheckSelfUpdate,
+    DownloadSelfUpdate,
+    ExtractSelfUpdate,
+    CheckUpdate,
+    Download,
+    Extract,
+    Verify,
+    Launch,
+    Uninstall,
+}
+
+impl Stage {
+    /// Text shown below the progress bar.
+    pub fn text(&self) -> &'static str {
+        match self {
+            Stage::CheckSelfUpdate => "正在检查启动器更新",
+            Stage::DownloadSelfUpdate => "正在下载启动器更新",
+            Stage::ExtractSelfUpdate => "正在解压启动器更新",
+            Stage::CheckUpdate => "正在检查 LLC 更新",
+            Stage::Download => "正在下载 LLC",
+            Stage::Extract => "正在解压 LLC",
+            Stage::Verify => "正在校验 LLC 文件",
+            Stage::Launch => "正在启动 Limbus Company",
+            Stage::Uninstall => "正在卸载 LLC",
+        }
+    }
+
+    /// Portion of the overall progress bar covered by this stage.
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            Stage::CheckSelfUpdate => (0.0, 0.1),
+            Stage::DownloadSelfUpdate => (0.1, 0.9),
+            Stage::ExtractSelfUpdate => (0.9, 1.0),
+            Stage::CheckUpdate => (0.0, 0.1),
+            Stage::Download => (0.1, 0.8),
+            Stage::Extract => (0.8, 0.95),
+            Stage::Verify => (0.8, 0.95),
+            Stage::Launch => (0.95, 1.0),
+            Stage::Uninstall => (0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// A new stage has started.
+    Stage(Stage),
+    /// Bytes downloaded so far in the current stage.
+    Download { downloaded: u64, total: Option<u64> },
+    /// Files extracted so far in the current stage.
+    Extract { extracted: u64, total: u64 },
+    /// Update checks were skipped, the installed version is used.
+    Offline,
+}
+
+/// Sending half of the progress channel, cheap to clone into tasks.
+#[derive(Debug, Clone)]
+pub struct ProgressSender(broadcast::Sender<ProgressEvent>);
+
+pub type ProgressReceiver = broadcast::Receiver<ProgressEvent>;
+
+pub fn channel() -> (ProgressSender, ProgressReceiver) {
+    // Only the latest events matter, a lagging receiver simply skips ahead.
+    let (tx, rx) = broadcast::channel(256);
+    (ProgressSender(tx), rx)
+}
+
+impl ProgressSender {
+    pub fn stage(&self, stage: Stage) {
+        trace!("progress stage: {stage:?}");
+        self.0.send(ProgressEvent::Stage(stage)).ok();
+    }
+
+    pub fn download(&self, downloaded: u64, total: Option<u64>) {
+        self.0
+            .send(ProgressEvent::Download { downloaded, total })
+            .ok();
+    }
+
+    pub fn extract(&self, extracted: u64, total: u64) {
+        self.0
+            .send(ProgressEvent::Extract { extracted, total })
+            .ok();
+    }
+
+    pub fn offline(&self) {
+        self.0.send(ProgressEvent::Offline).ok();
+    }
+}
+
+impl DownloadObserver for ProgressSender {
+    fn on_progress(&mut self, received: u64, total: Option<u64>) {
+        self.download(received, total)
+    }
+}
+
+/// Latest known progress, as tracked by the splash screen.
+#[derive(Debug, Default)]
+pub struct ProgressState {
+    stage: Option<Stage>,
+    fraction: Option<f32>,
+    detail: Option<String>,
+    offline: bool,
+}
+
+impl ProgressState {
+    /// Drain all pending events from the receiver.
+    pub fn poll(&mut self, rx: &mut ProgressReceiver) {
+        loop {
+            match rx.try_recv() {
+                Ok(event) => self.apply(event),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn apply(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Stage(stage) => {
+                self.stage = Some(stage);
+                self.fraction = None;
+                self.detail = None;
+            }
+            ProgressEvent::Download { downloaded, total } => {
+                self.fraction = total
+                    .filter(|total| *total > 0)
+                    .map(|total| downloaded as f32 / total as f32);
+                self.detail = Some(match total {
+                    Some(total) => format!("{} / {}", format_size(downloaded), format_size(total)),
+                    None => format_size(downloaded),
+                });
+            }
+            ProgressEvent::Extract { extracted, total } => {
+                self.fraction = (total > 0).then(|| extracted as f32 / total as f32);
+                self.detail = Some(format!("{extracted} / {total}"));
+            }
+            ProgressEvent::Offline => self.offline = true,
+        }
+    }
+
+    /// Overall progress in `0.0..=1.0`.
+    pub fn overall(&self) -> f32 {
+        let Some(stage) = self.stage else {
+            return 0.0;
+        };
+        let (start, end) = stage.range();
+        start + (end - start) * self.fraction.unwrap_or_default().clamp(0.0, 1.0)
+    }
+
+    /// Stage text with details, if any stage has started.
+    pub fn text(&self) -> Option<String> {
+        let stage = self.stage?;
+        let text = match &self.detail {
+            Some(detail) => format!("{}  {detail}", stage.text()),
+            None => stage.text().to_string(),
+        };
+        Some(if self.offline {
+            format!("[离线模式] {text}")
+        } else {
+            text
+        })
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const KIB: f64 = 1024.0;
+    const MIB: f64 = KIB * 1024.0;
+    let bytes = bytes as f64;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes / MIB)
+    } else {
+        format!("{:.1} KiB", bytes / KIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_state() {
+        let (tx, mut rx) = channel();
+        let mut state = ProgressState::default();
+        state.poll(&mut rx);
+        assert_eq!(state.overall(), 0.0);
+        assert!(state.text().is_none());
+
+        tx.stage(Stage::Download);
+        tx.download(512 * 1024, Some(1024 * 1024));
+        state.poll(&mut rx);
+        assert!((state.overall() - 0.45).abs() < f32::EPSILON);
+        assert_eq!(state.text().unwrap(), "正在下载 LLC  512.0 KiB / 1.0 MiB");
+
+        tx.stage(Stage::Extract);
+        state.poll(&mut rx);
+        assert_eq!(state.overall(), 0.8);
+        tx.extract(3, 3);
+        state.poll(&mut rx);
+        assert_eq!(state.overall(), 0.95);
+
+        // offline sticks across stages
+        tx.offline();
+        tx.stage(Stage::Launch);
+        state.poll(&mut rx);
+        assert_eq!(state.text().unwrap(), "[离线模式] 正在启动 Limbus Company");
+    }
+}
@@ -4,8 +4,15 @@ use directories::ProjectDirs;
 use eyre::Context;
 use flate2::read::GzDecoder;
 
-use bytes::Bytes;
-use llc_rs::{LLCConfig, npm::NpmClient};
+use crate::{
+    archive,
+    progress::{ProgressSender, Stage},
+};
+use llc_rs::{
+    LLCConfig,
+    npm::{NpmClient, NpmError, VersionSpec},
+    utils::CancellationToken,
+};
 use semver::Version;
 use std::{
     path::Path,
@@ -18,58 +25,139 @@ const PKG_NAME: &str = "@lightsing/llc-launcher-rs-win32";
 #[cfg(target_os = "linux")]
 const PKG_NAME: &str = "@lightsing/llc-launcher-rs-linux";
 
+/// Set for the tool when the launcher found the registries unreachable.
+pub const OFFLINE_ENV: &str = "LLC_OFFLINE";
+
 #[cfg(target_os = "windows")]
 const EXECUTABLE_NAME: &str = "llc-launcher-rs.exe";
 #[cfg(target_os = "linux")]
 const EXECUTABLE_NAME: &str = "llc-launcher-rs";
 
-pub async fn run(dirs: &ProjectDirs, self_path: &Path, config: &LLCConfig) -> eyre::Result<()> {
-    let client = NpmClient::new(config.npm_registries());
+/// Update the launcher if needed and hand over to the tool, skipping the update if `offline`.
+pub async fn run(
+    dirs: &ProjectDirs,
+    self_path: &Path,
+    config: &LLCConfig,
+    offline: bool,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    let tool_path = dirs.cache_dir().join(EXECUTABLE_NAME);
+    if offline {
+        warn!("Offline mode is enabled, skipping the launcher update check.");
+        return launch_self_as_tool(self_path, &tool_path, true).await;
+    }
+
+    progress.stage(Stage::CheckSelfUpdate);
+    let client = NpmClient::from_config(config)
+        .inspect_err(|e| error!("Failed to create npm client: {e}"))
+        .context("无法创建网络客户端")?
+        .with_cache_dir(dirs.cache_dir());
 
     let self_version = Version::parse(env!("CARGO_PKG_VERSION"))?;
-    let latest = client
-        .get_lastest_version(PKG_NAME)
-        .await
+    let packument = match client.get_abbreviated_packument(PKG_NAME).await {
+        Ok(packument) => packument,
+        Err(NpmError::Http(e)) => {
+            warn!("No registry is reachable, skipping the launcher update: {e}");
+            return launch_self_as_tool(self_path, &tool_path, true).await;
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("Failed to get launcher metadata: {e}"))
+                .context("无法获取最新版本信息，请检查网络连接。");
+        }
+    };
+    if let Some(reason) = packument
+        .version(&self_version)
+        .and_then(|meta| meta.deprecated.as_ref())
+    {
+        warn!("Current launcher {self_version} is deprecated: {reason}");
+    }
+    let latest = packument
+        .resolve(&VersionSpec::latest())
+        .cloned()
         .inspect_err(|e| error!("Failed to get latest version: {e}"))
         .context("无法获取最新版本信息，请检查网络连接。")?;
 
-    let tool_path = dirs.cache_dir().join(EXECUTABLE_NAME);
-
     if self_version >= latest.version {
         info!("Current version is up-to-date: {}", self_version);
-        fs::copy(&self_path, &tool_path)
-            .await
-            .inspect_err(|e| error!("Failed to copy self to tool path: {e}"))
-            .context("无法更新启动器可执行文件")?;
-        info!(
-            "Copied self({}) to tool path: {}",
-            self_path.display(),
-            tool_path.display()
-        );
-        launch_tool(&tool_path, self_path)
+        return launch_self_as_tool(self_path, &tool_path, false).await;
     }
 
     info!(
         "Current version: {}, Latest version: {}",
         self_version, latest.version
     );
-    let tarball = client
-        .download_dist(latest.dist)
+    match client.verify(PKG_NAME, &latest).await {
+        Ok(()) => {}
+        // the registry keys couldn't be fetched
+        Err(NpmError::Http(e)) => {
+            warn!("Cannot verify the launcher update, skipping it: {e}");
+            return launch_self_as_tool(self_path, &tool_path, true).await;
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("Failed to verify launcher update: {e}"))
+                .context("启动器更新签名校验失败");
+        }
+    }
+    progress.stage(Stage::DownloadSelfUpdate);
+    let tarball = match client
+        .download_dist_with_progress(
+            &latest.dist,
+            dirs.cache_dir(),
+            progress.clone(),
+            &CancellationToken::new(),
+        )
         .await
-        .inspect_err(|e| error!("failed to download tarball: {e}"))
-        .context("无法下载更新包")?;
+    {
+        Ok(tarball) => tarball,
+        Err(NpmError::Http(e)) => {
+            warn!("Cannot download the launcher update, skipping it: {e}");
+            return launch_self_as_tool(self_path, &tool_path, true).await;
+        }
+        Err(e) => {
+            return Err(e)
+                .inspect_err(|e| error!("failed to download tarball: {e}"))
+                .context("无法下载更新包");
+        }
+    };
 
-    extract_update(tarball, dirs).await?;
-    launch_tool(&tool_path, self_path)
+    progress.stage(Stage::ExtractSelfUpdate);
+    extract_update(&tarball, dirs.cache_dir()).await?;
+    fs::remove_file(&tarball).await.ok();
+    launch_tool(&tool_path, self_path, false)
+}
+
+/// Run the current executable as the tool.
+async fn launch_self_as_tool(
+    self_path: &Path,
+    tool_path: &Path,
+    offline: bool,
+) -> eyre::Result<()> {
+    fs::copy(&self_path, &tool_path)
+        .await
+        .inspect_err(|e| error!("Failed to copy self to tool path: {e}"))
+        .context("无法更新启动器可执行文件")?;
+    info!(
+        "Copied self({}) to tool path: {}",
+        self_path.display(),
+        tool_path.display()
+    );
+    launch_tool(tool_path, self_path, offline)
 }
 
-fn launch_tool(tool_path: &Path, self_path: &Path) -> ! {
+/// Start the tool and exit, telling it to stay `offline` so it doesn't wait for the registries
+/// again.
+fn launch_tool(tool_path: &Path, self_path: &Path, offline: bool) -> ! {
     let args: Vec<_> = std::env::args_os().skip(1).collect();
 
     info!("Launching tool at: {}", tool_path.display());
-    Command::new(tool_path)
-        .args(args)
-        .env("LLC_LAUNCHER_PATH", self_path)
+    let mut command = Command::new(tool_path);
+    command.args(args).env("LLC_LAUNCHER_PATH", self_path);
+    if offline {
+        command.env(OFFLINE_ENV, "1");
+    }
+    command
         .spawn()
         .inspect_err(|e| error!("Failed to launch tool: {e}"))
         .ok();
@@ -77,10 +165,35 @@ fn launch_tool(tool_path: &Path, self_path: &Path) -> ! {
     exit(0);
 }
 
-#[instrument(skip(tarball, dirs))]
-async fn extract_update(tarball: Bytes, dirs: &ProjectDirs) -> eyre::Result<()> {
-    let tar = GzDecoder::new(tarball.as_ref());
+/// Extract the executable of the update into `cache_dir` as the tool.
+///
+/// The executable is only moved into place once every entry of the archive was accepted.
+#[instrument(skip(tarball, cache_dir))]
+async fn extract_update(tarball: &Path, cache_dir: &Path) -> eyre::Result<()> {
+    let staged = cache_dir.join(format!("{EXECUTABLE_NAME}.new"));
+    let result = stage_update(tarball, &staged).and_then(|()| {
+        if !staged.exists() {
+            return Ok(());
+        }
+        std::fs::rename(&staged, cache_dir.join(EXECUTABLE_NAME))
+            .inspect_err(|e| error!("Failed to move the update into place: {e}"))
+            .context("无法更新启动器可执行文件")
+    });
+    if result.is_err() {
+        std::fs::remove_file(&staged).ok();
+    }
+    result
+}
+
+/// Check every entry of the update, unpacking the executable to `staged`.
+fn stage_update(tarball: &Path, staged: &Path) -> eyre::Result<()> {
+    let tar = GzDecoder::new(
+        std::fs::File::open(tarball)
+            .inspect_err(|e| error!("Failed to open tarball: {e}"))
+            .context("无法打开更新包")?,
+    );
     let mut archive = tar::Archive::new(tar);
+    let mut rejected = vec![];
     for file in archive
         .entries()
         .inspect_err(|e| error!("Failed to read archive: {e}"))
@@ -89,48 +202,95 @@ async fn extract_update(tarball: Bytes, dirs: &ProjectDirs) -> eyre::Result<()>
         let mut file = file
             .inspect_err(|e| error!("Failed to read archive entry: {e}"))
             .context("无法获取更新包条目")?;
-        if file
-            .path()
-            .inspect_err(|e| error!("Failed to get entry path: {e}"))
-            .context("无法获取更新包条目路径")?
-            .ends_with(EXECUTABLE_NAME)
-        {
-            let path = dirs.cache_dir().join(EXECUTABLE_NAME);
-            file.unpack(&path)
+        let path = match archive::check_entry(&file) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Rejected update entry {e}");
+                rejected.push(e);
+                continue;
+            }
+        };
+        if path.ends_with(EXECUTABLE_NAME) && file.header().entry_type().is_file() {
+            archive::unpack(&mut file, staged)
                 .inspect_err(|e| error!("Failed to unpack entry: {e}"))
                 .context("无法解压更新包条目")?;
-            let file_time = filetime::FileTime::now();
-            filetime::set_file_atime(&path, file_time)
-                .and_then(|_| filetime::set_file_mtime(&path, file_time))
-                .inspect_err(|e| error!("Failed to set file modification time: {e}"))
-                .context("无法设置文件时间")?;
         }
     }
-    Ok(())
+    archive::ensure_none_rejected(&rejected)
+        .inspect_err(|e| error!("Refusing the launcher update: {e}"))
+        .context("更新包包含不安全的条目")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use llc_rs::test_support::{MockRegistry, Package, TempDir, mock_config, npm_tarball};
     use tokio::test;
 
     #[test]
     async fn test_get_latest_version() {
-        let config = LLCConfig::default();
-        let client = NpmClient::new(&config.npm_registries());
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "0.1.0", *b""));
+        registry.publish(Package::new(PKG_NAME, "99.0.0", *b""));
+        let config = mock_config(&[&registry]);
+        let client = NpmClient::from_config(&config).unwrap();
 
         let version = client.get_lastest_version(PKG_NAME).await.unwrap();
-        println!("[test_get_latest_version] Latest version: {version:?}");
+        assert_eq!(version.version, Version::new(99, 0, 0));
     }
 
     #[test]
     async fn test_download_update() {
-        let config = LLCConfig::default();
-        let client = NpmClient::new(&config.npm_registries());
-        let dirs = ProjectDirs::from("com", "lightsing", "llc-launcher-rs").unwrap();
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(
+            PKG_NAME,
+            "99.0.0",
+            npm_tarball(&[(EXECUTABLE_NAME, b"launcher")]),
+        ));
+        let config = mock_config(&[&registry]);
+        let client = NpmClient::from_config(&config).unwrap();
+        let download_dir = TempDir::new("llc-launcher-self-update");
 
         let version = client.get_lastest_version(PKG_NAME).await.unwrap();
-        let tarball = client.download_dist(version.dist).await.unwrap();
-        extract_update(tarball, &dirs).await.unwrap();
+        let tarball = client
+            .download_dist(&version.dist, &download_dir)
+            .await
+            .unwrap();
+        extract_update(&tarball, &download_dir).await.unwrap();
+        assert_eq!(
+            std::fs::read(download_dir.join(EXECUTABLE_NAME)).unwrap(),
+            b"launcher"
+        );
+    }
+
+    #[test]
+    async fn test_extract_rejected_update() {
+        let dir = TempDir::new("llc-launcher-rejected-update");
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(8);
+        header.set_mode(0o755);
+        builder
+            .append_data(
+                &mut header,
+                format!("package/{EXECUTABLE_NAME}"),
+                &b"launcher"[..],
+            )
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "package/link", "/etc/passwd")
+            .unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gz, &builder.into_inner().unwrap()).unwrap();
+        let tarball = dir.join("update.tgz");
+        std::fs::write(&tarball, gz.finish().unwrap()).unwrap();
+
+        // the executable comes before the refused entry, but is never put in place
+        assert!(extract_update(&tarball, &dir).await.is_err());
+        assert!(!dir.join(EXECUTABLE_NAME).exists());
+        assert!(!dir.join(format!("{EXECUTABLE_NAME}.new")).exists());
     }
 }
@@ -1,4 +1,8 @@
-use crate::utils::{consume_next_error, next_error};
+use crate::{
+    progress::{ProgressReceiver, ProgressState},
+    uninstall::{self, UninstallChoice},
+    utils::{consume_next_error, next_error},
+};
 use eframe::{
     CreationContext, egui,
     egui::{CentralPanel, Color32, Frame, Layout, ViewportCommand},
@@ -10,6 +14,7 @@ use egui::{
     Stroke, UiBuilder, Vec2, Widget, pos2, vec2,
 };
 use std::{f32::consts::PI, time::Instant};
+use tokio::sync::oneshot;
 
 mod color;
 mod font;
@@ -20,7 +25,12 @@ const CENTER: Pos2 = pos2(640.0, 400.0);
 
 pub struct SplashScreen {
     shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    progress_rx: ProgressReceiver,
+    /// Pending uninstall confirmation, answered by the dialog.
+    uninstall_confirm: Option<oneshot::Sender<UninstallChoice>>,
+    show_uninstall: bool,
     should_quit: bool,
+    progress_state: ProgressState,
     progress: f32,
     show_animation: bool,
     scale: f32,
@@ -34,13 +44,19 @@ impl SplashScreen {
         cc: &CreationContext,
         is_tool: bool,
         shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+        progress_rx: ProgressReceiver,
+        uninstall_confirm: Option<oneshot::Sender<UninstallChoice>>,
     ) -> Self {
         font::load(&cc.egui_ctx);
         style::setup(&cc.egui_ctx);
 
         Self {
             shutdown_rx,
+            progress_rx,
+            show_uninstall: uninstall_confirm.is_some() && uninstall::requested(),
+            uninstall_confirm,
             should_quit: false,
+            progress_state: ProgressState::default(),
             progress: 0.0,
             show_animation: is_tool,
             scale: 1.0,
@@ -81,14 +97,14 @@ impl SplashScreen {
         }
     }
 
-    fn update_fake_progress(&mut self, ctx: &egui::Context) {
+    fn update_progress(&mut self, ctx: &egui::Context) {
+        self.progress_state.poll(&mut self.progress_rx);
+
         let dt = ctx.input(|i| i.stable_dt).min(0.1);
         if !self.should_quit {
-            if self.progress < 0.8 {
-                self.progress += dt * 0.4;
-            } else if self.progress < 0.99 {
-                self.progress += dt * 0.02;
-            }
+            // ease towards the reported progress so the bar doesn't jump between chunks
+            let target = self.progress_state.overall();
+            self.progress += (target - self.progress) * (dt * 8.0).min(1.0);
         } else if self.progress < 1.0 {
             self.progress += dt * 3.0;
         }
@@ -397,12 +413,131 @@ impl SplashScreen {
                     consume_next_error();
                     ui.ctx().send_viewport_cmd(ViewportCommand::Close);
                 }
+
+                // only while the worker still waits for an answer
+                if self
+                    .uninstall_confirm
+                    .as_ref()
+                    .is_some_and(|confirm| !confirm.is_closed())
+                {
+                    ui.add_space(20.0 * self.scale);
+                    if self
+                        .draw_button(
+                            ui,
+                            "卸载 LLC",
+                            color::RED,
+                            color::WHITE,
+                            color::BLACK,
+                            color::RED,
+                            Some(color::RED),
+                        )
+                        .clicked()
+                    {
+                        consume_next_error();
+                        self.show_uninstall = true;
+                    }
+                }
                 // ui.add_space(20.0 * self.scale);
                 // self.draw_button(ui, "复制崩溃日志", color::RED, color::WHITE, color::BLACK, color::RED, Some(color::RED));
             });
         });
     }
 
+    fn paint_uninstall_window(&mut self, ui: &mut egui::Ui) {
+        let rect = ui.max_rect();
+        ui.painter()
+            .rect_filled(rect, 0.0, Color32::from_black_alpha(110));
+
+        let modal_pos_phys = rect.left_top() + CENTER.to_vec2() * self.scale;
+        let modal_size = vec2(900.0 * self.scale, 360.0 * self.scale);
+        let modal_rect = Rect::from_center_size(modal_pos_phys, modal_size);
+        ui.painter()
+            .rect_filled(modal_rect, 0.0, Color32::from_black_alpha(240));
+        ui.painter().rect_stroke(
+            modal_rect,
+            0.0,
+            Stroke::new(2.0 * self.scale, color::GOLD),
+            StrokeKind::Inside,
+        );
+
+        // top gold bar
+        let top_bar_height = 60.0 * self.scale;
+        let top_bar_rect =
+            Rect::from_min_size(modal_rect.min, vec2(modal_rect.width(), top_bar_height));
+        ui.painter().rect_filled(top_bar_rect, 0.0, color::GOLD);
+        ui.painter().text(
+            top_bar_rect.left_center() + vec2(20.0 * self.scale, 0.0),
+            Align2::LEFT_CENTER,
+            "卸载 LLC",
+            FontId::new(30.0 * self.scale, font::SANS_SERIF_BOLD.clone()),
+            color::BLACK,
+        );
+
+        ui.painter().text(
+            pos2(
+                modal_rect.min.x + 40.0 * self.scale,
+                top_bar_rect.max.y + 45.0 * self.scale,
+            ),
+            Align2::LEFT_TOP,
+            "将从游戏中移除 LLC 的汉化文件与字体，并恢复游戏的语言设置。\n\
+             选择“卸载并清除数据”还会删除启动器的缓存、配置与日志。",
+            FontId::new(18.0 * self.scale, font::SANS_SERIF.clone()),
+            color::WHITE,
+        );
+
+        let bottom_rect = Rect::from_min_size(
+            pos2(modal_rect.min.x, modal_rect.max.y - 100.0 * self.scale),
+            vec2(modal_rect.width(), 100.0 * self.scale),
+        )
+        .shrink(10.0 * self.scale);
+        let mut choice = None;
+        ui.scope_builder(UiBuilder::new().max_rect(bottom_rect), |ui| {
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                ui.add_space(10.0 * self.scale);
+
+                let buttons = [
+                    ("取消", UninstallChoice::Cancel),
+                    ("卸载并清除数据", UninstallChoice::Purge),
+                    ("卸载", UninstallChoice::Uninstall),
+                ];
+                for (text, button_choice) in buttons {
+                    let response = if button_choice == UninstallChoice::Cancel {
+                        self.draw_button(
+                            ui,
+                            text,
+                            color::GOLD,
+                            color::WHITE,
+                            color::BLACK,
+                            color::BLACK,
+                            Some(color::GOLD),
+                        )
+                    } else {
+                        self.draw_button(
+                            ui,
+                            text,
+                            color::WHITE,
+                            color::WHITE,
+                            color::DARK_RED,
+                            color::RED,
+                            None,
+                        )
+                    };
+                    if response.clicked() {
+                        choice = Some(button_choice);
+                    }
+                    ui.add_space(20.0 * self.scale);
+                }
+            });
+        });
+
+        if let Some(choice) = choice
+            && let Some(confirm) = self.uninstall_confirm.take()
+        {
+            confirm.send(choice).ok();
+            self.show_uninstall = false;
+        }
+    }
+
     fn paint_progress_bar(&mut self, ui: &mut egui::Ui) {
         let rect = ui.max_rect();
         let bar_width = 1000.0 * self.scale;
@@ -419,6 +554,16 @@ impl SplashScreen {
             10.0 * self.scale,
             color::GOLD,
         );
+
+        if let Some(text) = self.progress_state.text() {
+            ui.painter().text(
+                bar_rect.left_bottom() + vec2(0.0, 12.0 * self.scale),
+                Align2::LEFT_TOP,
+                text,
+                FontId::new(16.0 * self.scale, font::SANS_SERIF.clone()),
+                color::GOLD,
+            );
+        }
     }
 }
 
@@ -436,7 +581,9 @@ impl eframe::App for SplashScreen {
 
         let next_error = next_error();
         if !self.show_animation {
-            ctx.send_viewport_cmd(ViewportCommand::Visible(next_error.is_some()));
+            ctx.send_viewport_cmd(ViewportCommand::Visible(
+                next_error.is_some() || self.show_uninstall,
+            ));
         }
 
         // let height = ctx.content_rect().height();
@@ -446,7 +593,7 @@ impl eframe::App for SplashScreen {
         // let font_small = height * 0.02;
 
         self.update_resolution(ctx);
-        self.update_fake_progress(ctx);
+        self.update_progress(ctx);
 
         let elapsed = self.start_time.elapsed().as_secs_f32();
 
@@ -472,7 +619,9 @@ impl eframe::App for SplashScreen {
                 self.paint_clock(ui, next_error.is_some(), elapsed);
                 self.paint_logo(ui, next_error.is_some());
 
-                if let Some(error) = next_error {
+                if self.show_uninstall {
+                    self.paint_uninstall_window(ui);
+                } else if let Some(error) = next_error {
                     self.paint_error_window(ui, error);
                 } else {
                     self.paint_progress_bar(ui);
@@ -0,0 +1,236 @@
+//! Removal of everything the launcher installed, restoring the game to vanilla.
+
+use crate::{
+    archive, game_lang,
+    llc::{self, PackPaths},
+    manifest::{MANIFEST_FILE, Manifest},
+    progress::{ProgressSender, Stage},
+};
+use directories::ProjectDirs;
+use eyre::Context;
+use llc_rs::{LLCConfig, get_limbus_company_install_path};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+use tokio::sync::oneshot;
+
+/// Answer to the uninstall confirmation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UninstallChoice {
+    Cancel,
+    /// Remove LLC from the game.
+    Uninstall,
+    /// Remove LLC and the launcher's cache, config and data directories.
+    Purge,
+}
+
+/// Whether `--uninstall` was given, opening the uninstall dialog right away.
+pub fn requested() -> bool {
+    std::env::args_os().any(|arg| arg == "--uninstall")
+}
+
+/// Uninstall LLC once the user confirmed it.
+pub async fn run(
+    dirs: &ProjectDirs,
+    self_path: &Path,
+    llc_config: &LLCConfig,
+    confirm: oneshot::Receiver<UninstallChoice>,
+    progress: ProgressSender,
+) -> eyre::Result<()> {
+    let choice = confirm.await.unwrap_or(UninstallChoice::Cancel);
+    if choice == UninstallChoice::Cancel {
+        info!("Uninstall cancelled.");
+        return Ok(());
+    }
+
+    progress.stage(Stage::Uninstall);
+    let game_root = get_limbus_company_install_path()
+        .inspect_err(|e| error!("failed to get Limbus Company install path: {e}"))
+        .context("无法获取 Limbus Company 安装路径")?;
+    let lang_dir = llc::lang_dir(&game_root);
+    for pack in llc_config.language_packs() {
+        remove_llc(&PackPaths::new(&lang_dir, pack))
+            .inspect_err(|e| error!("Failed to remove {}: {e}", pack.package()))
+            .context("无法卸载 LLC")?;
+    }
+    game_lang::restore_previous(&lang_dir, dirs.data_dir(), llc_config.language_packs())
+        .inspect_err(|e| error!("Failed to reset the game language: {e}"))
+        .context("无法重置游戏语言设置")?;
+    info!("LLC uninstalled from {}", game_root.display());
+
+    if choice == UninstallChoice::Purge {
+        for dir in [dirs.cache_dir(), dirs.config_dir(), dirs.data_dir()] {
+            remove_launcher_dir(dir, self_path);
+        }
+        info!("Launcher directories removed.");
+    }
+    Ok(())
+}
+
+/// Remove the installed release, its staging and backup directories.
+///
+/// With a manifest only the files of the release and the installed font are removed, so files
+/// the user added stay. Without one the whole directory is removed.
+fn remove_llc(paths: &PackPaths) -> eyre::Result<()> {
+    let llc_dir = &paths.installed;
+    match Manifest::load(llc_dir)? {
+        Some(manifest) => {
+            let installed = manifest
+                .files
+                .keys()
+                .map(String::as_str)
+                .chain([llc::INSTALLED_FONT, MANIFEST_FILE]);
+            for key in installed {
+                // the manifest is in the game directory, anyone may have edited it
+                match archive::check_path(Path::new(key)) {
+                    Ok(path) => remove_file(&llc_dir.join(path))?,
+                    Err(e) => warn!("Skipping manifest entry {key}: {e}"),
+                }
+            }
+            remove_empty_dirs(llc_dir)?;
+            if llc_dir.exists() {
+                warn!(
+                    "Kept files not installed by the launcher in {}",
+                    llc_dir.display()
+                );
+            }
+        }
+        None => remove_dir(llc_dir)?,
+    }
+    remove_dir(&paths.staging)?;
+    remove_dir(&paths.backup)?;
+    Ok(())
+}
+
+/// Remove `dir`, except the running executable which can't be removed on Windows.
+fn remove_launcher_dir(dir: &Path, self_path: &Path) {
+    let result = if self_path.starts_with(dir) {
+        fs::read_dir(dir).and_then(|entries| {
+            for entry in entries {
+                let path = entry?.path();
+                if path == self_path {
+                    continue;
+                }
+                if path.is_dir() {
+                    remove_dir(&path)?;
+                } else {
+                    remove_file(&path)?;
+                }
+            }
+            Ok(())
+        })
+    } else {
+        remove_dir(dir)
+    };
+    // e.g. the log file in use
+    result
+        .inspect_err(|e| warn!("Failed to remove {}: {e}", dir.display()))
+        .ok();
+}
+
+/// Remove the empty directories below and including `dir`.
+fn remove_empty_dirs(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_dirs(&entry.path())?;
+        }
+    }
+    if fs::read_dir(dir)?.next().is_none() {
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn remove_dir(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+    use llc_rs::test_support::TempDir;
+    use semver::Version;
+
+    #[test]
+    fn test_remove_llc() {
+        let dir = TempDir::new("llc-launcher-uninstall");
+        let write = |path: &str, content: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("LLC_zh-CN/Info/version.json", "{}");
+        write("LLC_zh-CN/Font/Context/ChineseFont.ttf", "font");
+        write("LLC_zh-CN/Font/Title/user.ttf", "user font");
+        write("LLC_zh-CN.backup/Info/version.json", "{}");
+        let llc_config = LLCConfig::default();
+        let packs = llc_config.language_packs();
+        let paths = PackPaths::new(&dir, &packs[0]);
+        let llc_dir = &paths.installed;
+        let mut manifest = Manifest {
+            tag: "v1".to_string(),
+            version: Version::new(1, 0, 0),
+            files: Default::default(),
+        };
+        manifest.files.insert(
+            "Info/version.json".to_string(),
+            FileEntry::read(&llc_dir.join("Info/version.json")).unwrap(),
+        );
+        manifest.save(llc_dir).unwrap();
+
+        remove_llc(&paths).unwrap();
+        assert!(!llc_dir.join("Info").exists());
+        assert!(!llc_dir.join("Font/Context").exists());
+        assert!(!llc_dir.join(MANIFEST_FILE).exists());
+        assert!(llc_dir.join("Font/Title/user.ttf").exists());
+        assert!(!paths.backup.exists());
+
+        // without manifest the whole directory goes
+        remove_llc(&paths).unwrap();
+        assert!(!llc_dir.exists());
+    }
+
+    #[test]
+    fn test_remove_llc_traversal() {
+        let dir = TempDir::new("llc-launcher-uninstall-traversal");
+        let packs = LLCConfig::default().language_packs().to_vec();
+        let paths = PackPaths::new(&dir, &packs[0]);
+        let llc_dir = &paths.installed;
+        fs::create_dir_all(llc_dir).unwrap();
+        let outside = dir.join("outside.txt");
+        fs::write(&outside, "game file").unwrap();
+        let entry = FileEntry::read(&outside).unwrap();
+        let mut manifest = Manifest {
+            tag: "v1".to_string(),
+            version: Version::new(1, 0, 0),
+            files: Default::default(),
+        };
+        for key in ["../outside.txt", outside.to_str().unwrap()] {
+            manifest.files.insert(key.to_string(), entry.clone());
+        }
+        manifest.save(llc_dir).unwrap();
+
+        remove_llc(&paths).unwrap();
+        assert!(outside.exists());
+        assert!(!llc_dir.exists());
+    }
+}
@@ -1,11 +1,68 @@
-use crate::utils::ResultExt;
+use crate::utils::{ReqwestExtError, ResultExt};
+use reqwest::{ClientBuilder, NoProxy, Proxy, redirect};
+use semver::Version;
 use serde::{Deserialize, Serialize};
-use url::Url;
+use serde_with::{DurationMilliSeconds, DurationSeconds, serde_as};
+use std::{collections::BTreeMap, path::PathBuf, sync::LazyLock, time::Duration};
+use url::{Host, Url};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLCConfig {
     #[serde(default = "default_npm_registries", rename = "npm-registries")]
     npm_registries: Vec<Url>,
+    /// `lang-dir` of the language pack installed and selected in the game.
+    #[serde(default = "default_language")]
+    language: String,
+    /// Version of the selected language pack to install instead of the latest one, until it is
+    /// cleared.
+    #[serde(
+        default,
+        rename = "pinned-version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pinned_version: Option<Version>,
+    /// An `.npmrc` to read scope registries and tokens from, `scopes` and `registry-auth` take
+    /// precedence over it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    npmrc: Option<PathBuf>,
+    /// Registry of each `@scope`, used exclusively for the packages of that scope.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scopes: BTreeMap<String, Url>,
+    #[serde(
+        default,
+        rename = "registry-auth",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    registry_auth: Vec<RegistryAuth>,
+    #[serde(default, rename = "registry-selection")]
+    registry_selection: RegistrySelection,
+    #[serde(default)]
+    network: NetworkConfig,
+    #[serde(default)]
+    signatures: SignatureConfig,
+    #[serde(default, rename = "tarball-cache")]
+    tarball_cache: TarballCacheConfig,
+    #[serde(default = "default_language_packs", rename = "language-packs")]
+    language_packs: Vec<LanguagePack>,
+}
+
+/// Bearer token sent to every URL under `registry`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryAuth {
+    registry: Url,
+    /// The token, `${VAR}` is replaced by the environment variable `VAR`.
+    token: String,
+}
+
+impl RegistryAuth {
+    pub fn registry(&self) -> &Url {
+        &self.registry
+    }
+
+    /// The token as written in the config, before environment variables are expanded.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
 }
 
 impl LLCConfig {
@@ -13,16 +70,636 @@ impl LLCConfig {
     pub fn npm_registries(&self) -> &[Url] {
         &self.npm_registries
     }
+
+    /// Get the `.npmrc` to read scopes and tokens from.
+    pub fn npmrc(&self) -> Option<&PathBuf> {
+        self.npmrc.as_ref()
+    }
+
+    /// Get the registry of each scope.
+    pub fn scopes(&self) -> &BTreeMap<String, Url> {
+        &self.scopes
+    }
+
+    /// Get the tokens of private registries.
+    pub fn registry_auth(&self) -> &[RegistryAuth] {
+        &self.registry_auth
+    }
+
+    /// Get how the NPM registries are ordered.
+    pub fn registry_selection(&self) -> &RegistrySelection {
+        &self.registry_selection
+    }
+
+    /// Get the network settings.
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    /// Get how published versions are verified.
+    pub fn signatures(&self) -> &SignatureConfig {
+        &self.signatures
+    }
+
+    /// Get the limits of the tarball cache.
+    pub fn tarball_cache(&self) -> &TarballCacheConfig {
+        &self.tarball_cache
+    }
+
+    /// Get the known language packs.
+    pub fn language_packs(&self) -> &[LanguagePack] {
+        &self.language_packs
+    }
+
+    /// Get the selected language pack, `None` if no known pack has the selected `lang-dir`.
+    pub fn language_pack(&self) -> Option<&LanguagePack> {
+        self.language_packs
+            .iter()
+            .find(|pack| pack.lang_dir == self.language)
+    }
+
+    /// Select the language pack with `lang_dir`.
+    ///
+    /// Fails if no known pack has that `lang-dir`.
+    pub fn select_language(&mut self, lang_dir: &str) -> Result<(), UnknownLanguage> {
+        if !self
+            .language_packs
+            .iter()
+            .any(|pack| pack.lang_dir == lang_dir)
+        {
+            return Err(UnknownLanguage(lang_dir.to_string()));
+        }
+        if self.language != lang_dir {
+            // versions of one pack mean nothing for another
+            self.pinned_version = None;
+        }
+        self.language = lang_dir.to_string();
+        Ok(())
+    }
+
+    /// Get the pinned version of the selected language pack.
+    pub fn pinned_version(&self) -> Option<&Version> {
+        self.pinned_version.as_ref()
+    }
+
+    /// Pin the selected language pack to `version`, or follow the latest version again with
+    /// `None`.
+    pub fn set_pinned_version(&mut self, version: Option<Version>) {
+        self.pinned_version = version;
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("no language pack is installed into {0}")]
+pub struct UnknownLanguage(pub String);
+
+/// A translation published as npm package. The tarball contains it under
+/// `package/LimbusCompany_Data/Lang/<lang-dir>`, like the game's `Lang` directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LanguagePack {
+    package: String,
+    /// Folder of the pack in the game's `Lang` directory.
+    lang_dir: String,
+    #[serde(default)]
+    font: FontRequirement,
+}
+
+/// Font a language pack needs besides its own files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FontRequirement {
+    /// The pack ships its fonts or uses the game's.
+    #[default]
+    None,
+    /// The launcher's bundled Simplified Chinese font, installed unless the pack has a valid one.
+    BundledChinese,
+}
+
+impl LanguagePack {
+    pub fn new(
+        package: impl Into<String>,
+        lang_dir: impl Into<String>,
+        font: FontRequirement,
+    ) -> Self {
+        LanguagePack {
+            package: package.into(),
+            lang_dir: lang_dir.into(),
+            font,
+        }
+    }
+
+    /// Get the npm package.
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    /// Get the folder in the game's `Lang` directory.
+    pub fn lang_dir(&self) -> &str {
+        &self.lang_dir
+    }
+
+    /// Get the font the pack needs.
+    pub fn font(&self) -> FontRequirement {
+        self.font
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl LLCConfig {
+    /// Config for local test registries served over plain HTTP, retrying without delay.
+    ///
+    /// Registry keys are fetched from the first registry.
+    pub(crate) fn for_local_registries(npm_registries: Vec<Url>) -> Self {
+        let signatures = match npm_registries.first() {
+            Some(registry) => SignatureConfig {
+                keys_url: registry.join("-/npm/v1/keys").infallible(),
+                ..Default::default()
+            },
+            None => SignatureConfig::default(),
+        };
+        LLCConfig {
+            npm_registries,
+            language: default_language(),
+            pinned_version: None,
+            npmrc: None,
+            scopes: BTreeMap::new(),
+            registry_auth: vec![],
+            registry_selection: RegistrySelection::default(),
+            network: NetworkConfig {
+                connect_timeout: Duration::from_secs(2),
+                read_timeout: Duration::from_secs(5),
+                total_timeout: None,
+                retry: RetryPolicy {
+                    initial_backoff: Duration::ZERO,
+                    max_backoff: Duration::ZERO,
+                    jitter: false,
+                    ..Default::default()
+                },
+                proxy: ProxyConfig {
+                    use_system_proxy: false,
+                    ..Default::default()
+                },
+                allow_insecure_localhost: true,
+            },
+            signatures,
+            tarball_cache: TarballCacheConfig::default(),
+            language_packs: default_language_packs(),
+        }
+    }
 }
 
 impl Default for LLCConfig {
     fn default() -> Self {
         LLCConfig {
             npm_registries: default_npm_registries(),
+            language: default_language(),
+            pinned_version: None,
+            npmrc: None,
+            scopes: BTreeMap::new(),
+            registry_auth: vec![],
+            registry_selection: RegistrySelection::default(),
+            network: NetworkConfig::default(),
+            signatures: SignatureConfig::default(),
+            tarball_cache: TarballCacheConfig::default(),
+            language_packs: default_language_packs(),
         }
     }
 }
 
+/// How the NPM registries are ordered before they are tried.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RegistrySelection {
+    strategy: SelectionStrategy,
+    /// Time a registry gets to answer the probe before it is ranked last.
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "probe-timeout-ms")]
+    probe_timeout: Duration,
+    /// How long a ranking is reused before the registries are probed again, in seconds.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    cache_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// Try the registries in the configured order.
+    #[default]
+    Ordered,
+    /// Probe all registries concurrently and try the fastest first.
+    Fastest,
+}
+
+impl RegistrySelection {
+    /// Get the selection strategy.
+    pub fn strategy(&self) -> SelectionStrategy {
+        self.strategy
+    }
+
+    /// Get the probe timeout.
+    pub fn probe_timeout(&self) -> Duration {
+        self.probe_timeout
+    }
+
+    /// Get how long a ranking stays valid.
+    pub fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+}
+
+impl Default for RegistrySelection {
+    fn default() -> Self {
+        RegistrySelection {
+            strategy: SelectionStrategy::Ordered,
+            probe_timeout: Duration::from_secs(3),
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Timeouts, retry behaviour and proxies of the HTTP clients.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// Timeout for establishing a connection, in seconds.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    connect_timeout: Duration,
+    /// Timeout between two reads of a response, in seconds.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    read_timeout: Duration,
+    /// Timeout for a whole request including its body, in seconds. Unset by default since
+    /// tarball downloads on slow connections can legitimately take long.
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_timeout: Option<Duration>,
+    retry: RetryPolicy,
+    proxy: ProxyConfig,
+    /// Allow plain HTTP to loopback addresses, for local test registries. Every other host still
+    /// requires HTTPS. Not configurable, only set by [`LLCConfig::for_local_registries`].
+    #[serde(skip)]
+    allow_insecure_localhost: bool,
+}
+
+impl NetworkConfig {
+    /// Get the retry policy.
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Whether `url` may be requested, i.e. uses HTTPS or is an allowed plain HTTP loopback URL.
+    pub fn permits(&self, url: &Url) -> bool {
+        match url.scheme() {
+            "https" => true,
+            "http" => self.allow_insecure_localhost && is_loopback(url),
+            _ => false,
+        }
+    }
+
+    /// Get the proxy settings.
+    pub fn proxy(&self) -> &ProxyConfig {
+        &self.proxy
+    }
+
+    /// Apply the configured timeouts, proxies and HTTPS enforcement to a client builder.
+    ///
+    /// Fails if reqwest rejects a proxy URL.
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, reqwest::Error> {
+        let builder = self
+            .proxy
+            .apply(builder)?
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout);
+        let builder = if self.allow_insecure_localhost {
+            // reqwest can only enforce HTTPS for all hosts, so redirects are checked here and
+            // initial requests by the callers through `permits`
+            builder.redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= 10 {
+                    attempt.error("too many redirects")
+                } else if attempt.url().scheme() == "https" || is_loopback(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.error("plain HTTP is only allowed to loopback addresses")
+                }
+            }))
+        } else {
+            builder.https_only(true)
+        };
+        Ok(match self.total_timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        })
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            total_timeout: None,
+            retry: RetryPolicy::default(),
+            proxy: ProxyConfig::default(),
+            allow_insecure_localhost: false,
+        }
+    }
+}
+
+/// Proxies for reaching the registries, e.g. a local Clash or v2ray.
+///
+/// Proxy URLs may use the `http`, `https`, `socks5` or `socks5h` scheme, the latter resolving
+/// host names through the proxy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProxyConfig {
+    /// Proxy for all requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<Url>,
+    /// Proxy for plain HTTP requests, takes precedence over `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http: Option<Url>,
+    /// Proxy for HTTPS requests, takes precedence over `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    https: Option<Url>,
+    /// Hosts reached without a proxy, like entries of `NO_PROXY`: `registry.npmmirror.com`,
+    /// `.example.com` for subdomains, or an IP range such as `192.168.0.0/16`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    no_proxy: Vec<String>,
+    /// Credentials for proxies whose URL doesn't contain any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    /// Fall back to the proxy in the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`
+    /// environment variables when no proxy is configured here.
+    use_system_proxy: bool,
+}
+
+impl ProxyConfig {
+    /// Whether any proxy is configured explicitly.
+    pub fn is_configured(&self) -> bool {
+        self.url.is_some() || self.http.is_some() || self.https.is_some()
+    }
+
+    fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, reqwest::Error> {
+        if !self.is_configured() {
+            // reqwest reads the environment unless told otherwise
+            return Ok(if self.use_system_proxy {
+                builder
+            } else {
+                builder.no_proxy()
+            });
+        }
+        // more specific proxies first, reqwest uses the first one matching
+        let proxies = [
+            self.http.as_ref().map(|url| Proxy::http(url.as_str())),
+            self.https.as_ref().map(|url| Proxy::https(url.as_str())),
+            self.url.as_ref().map(|url| Proxy::all(url.as_str())),
+        ];
+        for proxy in proxies.into_iter().flatten() {
+            let mut proxy = proxy?.no_proxy(NoProxy::from_string(&self.no_proxy.join(",")));
+            if let Some(username) = &self.username {
+                proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or_default());
+            }
+            builder = builder.proxy(proxy);
+        }
+        Ok(builder)
+    }
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            url: None,
+            http: None,
+            https: None,
+            no_proxy: vec![],
+            username: None,
+            password: None,
+            use_system_proxy: true,
+        }
+    }
+}
+
+/// Verification of published versions beyond their integrity, see [`NpmClient::verify`].
+///
+/// [`NpmClient::verify`]: crate::npm::NpmClient::verify
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SignatureConfig {
+    /// Refuse versions without a valid registry signature, unless they come from a scope
+    /// registry other than the one of the keys.
+    verify: bool,
+    /// Where the registry keys are fetched from if a version is signed with a key the built-in
+    /// keys of registry.npmjs.org don't know, e.g. after a rotation. Mirrors serve the
+    /// signatures of the original registry, so its keys are fetched from there and not from the
+    /// mirrors.
+    keys_url: Url,
+    /// Registry keys to use instead of the built-in and fetched ones, in the form of
+    /// `/-/npm/v1/keys`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keys: Vec<RegistryKey>,
+}
+
+/// Keys registry.npmjs.org signs versions with, as published at `/-/npm/v1/keys`, so that
+/// signatures can be checked when only a mirror is reachable.
+pub(crate) static NPM_REGISTRY_KEYS: LazyLock<Vec<RegistryKey>> = LazyLock::new(|| {
+    vec![
+        RegistryKey {
+            keyid: "SHA256:jl3bwswu80PjjokCgh0o2w5c2U4LhQAE57gj9cz1kzA".to_string(),
+            key: "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE1Olb3zMAFFxXKHiIkQO5cJ3Yhl5i6UPp+IhuteBJbuHcA5UogKo0EWtlWwW6KSaKoTNEYL7JlCQiVnkhBktUgg==".to_string(),
+            expires: Some("2025-01-29T00:00:00.000Z".to_string()),
+        },
+        RegistryKey {
+            keyid: "SHA256:DhQ8wR5APBvFHLF/+Tc+AYvPOdTpcIDqOhxsBHRwC7U".to_string(),
+            key: "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEY6Ya7W++7aUPzvMTrezH6Ycx3c+HOKYCcNGybJZSCJq/fd7Qa8uuAKtdIkUQtQiEKERhAmE5lMMJhP8OkDOa2g==".to_string(),
+            expires: None,
+        },
+    ]
+});
+
+impl SignatureConfig {
+    /// Whether registry signatures are verified.
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Get the URL of the registry keys.
+    pub fn keys_url(&self) -> &Url {
+        &self.keys_url
+    }
+
+    /// Get the pinned registry keys, empty if the built-in or fetched ones are used.
+    pub fn keys(&self) -> &[RegistryKey] {
+        &self.keys
+    }
+
+    /// Whether versions served by `registry` are signed with the registry keys.
+    pub fn covers(&self, registry: &Url) -> bool {
+        registry.origin() == self.keys_url.origin()
+    }
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        SignatureConfig {
+            verify: true,
+            keys_url: Url::parse("https://registry.npmjs.org/-/npm/v1/keys").infallible(),
+            keys: vec![],
+        }
+    }
+}
+
+/// A public key the registry signs versions with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryKey {
+    keyid: String,
+    /// Base64 encoded P-256 public key in SubjectPublicKeyInfo form.
+    key: String,
+    /// When the registry stopped signing with the key. Versions signed before stay valid, so
+    /// this is not checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+}
+
+impl RegistryKey {
+    pub fn new(keyid: impl Into<String>, key: impl Into<String>) -> Self {
+        RegistryKey {
+            keyid: keyid.into(),
+            key: key.into(),
+            expires: None,
+        }
+    }
+
+    pub fn keyid(&self) -> &str {
+        &self.keyid
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Limits of the downloaded tarballs kept in the cache directory, see
+/// [`NpmClient::with_cache_dir`].
+///
+/// [`NpmClient::with_cache_dir`]: crate::npm::NpmClient::with_cache_dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TarballCacheConfig {
+    /// Total size of the cached tarballs in MiB, the least recently used go first. `0` disables
+    /// the cache.
+    max_size_mb: u64,
+    /// Tarballs unused for this many days are removed.
+    max_age_days: u64,
+}
+
+impl TarballCacheConfig {
+    /// Get the size limit in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    /// Get how long an unused tarball is kept.
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_days.saturating_mul(24 * 60 * 60))
+    }
+}
+
+impl Default for TarballCacheConfig {
+    fn default() -> Self {
+        TarballCacheConfig {
+            max_size_mb: 1024,
+            max_age_days: 90,
+        }
+    }
+}
+
+/// How often and how patiently a request is retried against the same URL before moving on to
+/// the next mirror.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RetryPolicy {
+    /// Attempts per URL, including the first one.
+    attempts: u32,
+    /// Delay before the first retry, doubled for each further retry.
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "initial-backoff-ms")]
+    initial_backoff: Duration,
+    /// Upper bound of the delay between retries.
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "max-backoff-ms")]
+    max_backoff: Duration,
+    /// Randomize delays so that many launchers don't retry in lockstep.
+    jitter: bool,
+    /// HTTP status codes worth retrying, anything else moves on to the next mirror.
+    retryable_status: Vec<u16>,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Attempts per URL, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.attempts.max(1)
+    }
+
+    /// Delay before retry number `retry`, starting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        if self.jitter {
+            delay.mul_f64(0.5 + fastrand::f64() * 0.5)
+        } else {
+            delay
+        }
+    }
+
+    /// Whether a failed request is worth another attempt against the same URL.
+    pub fn is_retryable(&self, err: &ReqwestExtError) -> bool {
+        match err {
+            ReqwestExtError::Reqwest(e) => match e.status() {
+                Some(status) => self.retryable_status.contains(&status.as_u16()),
+                None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            },
+            ReqwestExtError::InvalidRange => true,
+            _ => false,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            retryable_status: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
 pub(crate) fn default_npm_registries() -> Vec<Url> {
     vec![
         Url::parse("https://registry.npmmirror.com").infallible(),
@@ -30,6 +707,18 @@ pub(crate) fn default_npm_registries() -> Vec<Url> {
     ]
 }
 
+fn default_language() -> String {
+    "LLC_zh-CN".to_string()
+}
+
+fn default_language_packs() -> Vec<LanguagePack> {
+    vec![LanguagePack::new(
+        "@lightsing/llc-zh-cn",
+        "LLC_zh-CN",
+        FontRequirement::BundledChinese,
+    )]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -38,6 +727,46 @@ mod tests {
     "https://registry.npmmirror.com/",
     "https://registry.npmjs.org/",
 ]
+language = "LLC_zh-CN"
+
+[registry-selection]
+strategy = "ordered"
+probe-timeout-ms = 3000
+cache-ttl = 86400
+
+[network]
+connect-timeout = 10
+read-timeout = 30
+
+[network.retry]
+attempts = 3
+initial-backoff-ms = 500
+max-backoff-ms = 5000
+jitter = true
+retryable-status = [
+    408,
+    429,
+    500,
+    502,
+    503,
+    504,
+]
+
+[network.proxy]
+use-system-proxy = true
+
+[signatures]
+verify = true
+keys-url = "https://registry.npmjs.org/-/npm/v1/keys"
+
+[tarball-cache]
+max-size-mb = 1024
+max-age-days = 90
+
+[[language-packs]]
+package = "@lightsing/llc-zh-cn"
+lang-dir = "LLC_zh-CN"
+font = "bundled-chinese"
 "#;
     #[test]
     fn test_config() {
@@ -49,6 +778,158 @@ mod tests {
         assert_eq!(serialized, CURRENT_DEFAULT);
     }
 
+    #[test]
+    fn test_language_packs() {
+        let mut config: LLCConfig = toml::from_str(
+            r#"
+language = "LLC_zh-TW"
+
+[[language-packs]]
+package = "@lightsing/llc-zh-cn"
+lang-dir = "LLC_zh-CN"
+font = "bundled-chinese"
+
+[[language-packs]]
+package = "@example/llc-zh-tw"
+lang-dir = "LLC_zh-TW"
+"#,
+        )
+        .unwrap();
+        let pack = config.language_pack().unwrap();
+        assert_eq!(pack.package(), "@example/llc-zh-tw");
+        assert_eq!(pack.font(), FontRequirement::None);
+
+        config.select_language("LLC_zh-CN").unwrap();
+        assert_eq!(
+            config.language_pack().unwrap().package(),
+            "@lightsing/llc-zh-cn"
+        );
+        assert!(config.select_language("LLC_ja").is_err());
+        assert_eq!(config.language_pack(), config.language_packs().first());
+
+        // a pin is kept with the pack it was set for
+        config.set_pinned_version(Some(Version::new(1, 2, 0)));
+        let serialized = toml::to_string(&config).unwrap();
+        assert!(serialized.contains(r#"pinned-version = "1.2.0""#));
+        let mut config: LLCConfig = toml::from_str(&serialized).unwrap();
+        assert_eq!(config.pinned_version(), Some(&Version::new(1, 2, 0)));
+        config.select_language("LLC_zh-CN").unwrap();
+        assert!(config.pinned_version().is_some());
+        config.select_language("LLC_zh-TW").unwrap();
+        assert!(config.pinned_version().is_none());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+        assert_eq!(RetryPolicy::none().attempts(), 1);
+    }
+
+    #[test]
+    fn test_permits() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let mut network = NetworkConfig::default();
+        assert!(network.permits(&url("https://registry.npmjs.org/")));
+        assert!(!network.permits(&url("http://127.0.0.1:4873/")));
+        let configured: NetworkConfig = toml::from_str("allow-insecure-localhost = true").unwrap();
+        assert!(!configured.permits(&url("http://127.0.0.1:4873/")));
+
+        network.allow_insecure_localhost = true;
+        assert!(network.permits(&url("http://127.0.0.1:4873/")));
+        assert!(network.permits(&url("http://[::1]:4873/")));
+        assert!(network.permits(&url("http://localhost/")));
+        assert!(!network.permits(&url("http://registry.npmjs.org/")));
+        assert!(!network.permits(&url("http://192.168.1.1/")));
+        assert!(!network.permits(&url("ftp://127.0.0.1/")));
+    }
+
+    #[test]
+    fn test_proxy_config() {
+        let config: LLCConfig = toml::from_str(
+            r#"
+[network.proxy]
+url = "socks5h://127.0.0.1:7891"
+https = "http://127.0.0.1:7890"
+no-proxy = ["registry.npmmirror.com", ".example.com"]
+username = "user"
+password = "secret"
+"#,
+        )
+        .unwrap();
+        let proxy = config.network().proxy();
+        assert!(proxy.is_configured());
+        assert!(proxy.use_system_proxy);
+        assert_eq!(proxy.no_proxy.len(), 2);
+        config
+            .network()
+            .apply(ClientBuilder::default())
+            .unwrap()
+            .build()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_is_used() {
+        let proxy = crate::test_support::MockRegistry::start().await;
+        let mut network = NetworkConfig {
+            allow_insecure_localhost: true,
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        network.proxy.url = Some(proxy.url().clone());
+        let client = network
+            .apply(ClientBuilder::default())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        // the port is closed, the request only gets an answer through the proxy
+        client.get("http://127.0.0.1:9/pkg").send().await.unwrap();
+        assert_eq!(proxy.requests()[0].path, "http://127.0.0.1:9/pkg");
+
+        network.proxy.no_proxy = vec!["127.0.0.1".to_string()];
+        let client = network
+            .apply(ClientBuilder::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(client.get("http://127.0.0.1:9/pkg").send().await.is_err());
+        assert_eq!(proxy.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_scoped_registries() {
+        let config: LLCConfig = toml::from_str(
+            r#"
+npmrc = "/home/user/.npmrc"
+
+[scopes]
+"@lightsing" = "https://npm.example.com/"
+
+[[registry-auth]]
+registry = "https://npm.example.com/"
+token = "${LLC_NPM_TOKEN}"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.scopes()["@lightsing"].as_str(),
+            "https://npm.example.com/"
+        );
+        assert_eq!(config.registry_auth()[0].token(), "${LLC_NPM_TOKEN}");
+
+        let serialized = toml::to_string_pretty(&config).unwrap();
+        let reparsed: LLCConfig = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.npmrc(), config.npmrc());
+        assert_eq!(reparsed.scopes(), config.scopes());
+    }
+
     #[test]
     fn test_cross_version_compatibility() {
         let old_toml = r#"[settings]
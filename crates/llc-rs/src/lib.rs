@@ -1,5 +1,5 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
-#![deny(clippy::unwrap_used)]
+#![cfg_attr(not(test), deny(clippy::unwrap_used))]
 
 #[macro_use]
 extern crate tracing;
@@ -9,10 +9,21 @@ use reqwest::{Client, ClientBuilder, header, header::HeaderMap};
 use std::{path::PathBuf, sync::LazyLock};
 
 mod config;
-pub use config::LLCConfig;
+pub use config::{
+    FontRequirement, LLCConfig, LanguagePack, NetworkConfig, ProxyConfig, RegistryAuth,
+    RegistryKey, RegistrySelection, RetryPolicy, SelectionStrategy, SignatureConfig,
+    TarballCacheConfig, UnknownLanguage,
+};
 
+mod metadata_cache;
 pub mod npm;
+pub mod npmrc;
+mod ranking;
+pub mod signature;
 mod steam_support;
+mod tarball_cache;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 pub mod utils;
 
 pub use steam_support::{
@@ -49,7 +60,9 @@ pub static USER_AGENT: LazyLock<&str> = LazyLock::new(|| {
 });
 
 pub static DEFAULT_CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    ClientBuilder::default()
+    NetworkConfig::default()
+        .apply(ClientBuilder::default())
+        .infallible()
         .user_agent(*USER_AGENT)
         .default_headers(HeaderMap::from_iter([(
             header::FROM,
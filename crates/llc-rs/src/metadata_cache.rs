@@ -0,0 +1,48 @@
+//! Registry documents persisted on disk with their `ETag`, so that an unchanged package costs a
+//! `304 Not Modified` instead of a full download.
+
+use crate::utils::ResultExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Directory of the cached documents inside the cache directory.
+pub(crate) const METADATA_CACHE_DIR: &str = "npm-metadata";
+
+/// A document as last served by a registry.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CachedDocument {
+    /// URL the document was fetched from, its `ETag` is only sent back there.
+    pub url: Url,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    pub body: Value,
+}
+
+/// Path of the document `name` (e.g. `@scope/pkg.install-v1`) under `cache_dir`.
+pub(crate) fn document_path(cache_dir: &Path, name: &str) -> PathBuf {
+    cache_dir
+        .join(METADATA_CACHE_DIR)
+        .join(format!("{}.json", name.replace('/', "%2f")))
+}
+
+pub(crate) async fn load(path: &Path) -> Option<CachedDocument> {
+    let content = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&content)
+        .inspect_err(|e| warn!("ignoring corrupted cached document {}: {e}", path.display()))
+        .ok()
+}
+
+pub(crate) async fn store(path: &Path, document: &CachedDocument) {
+    let write = async {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, serde_json::to_vec(document).infallible()).await
+    };
+    write
+        .await
+        .inspect_err(|e| warn!("failed to cache document {}: {e}", path.display()))
+        .ok();
+}
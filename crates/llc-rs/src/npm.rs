@@ -1,139 +1,1516 @@
 use crate::{
-    USER_AGENT,
-    utils::{ClientExt, ReqwestExtError, ResultExt},
+    LLCConfig, NetworkConfig, RegistryKey, RegistrySelection, SelectionStrategy, SignatureConfig,
+    TarballCacheConfig, USER_AGENT,
+    config::NPM_REGISTRY_KEYS,
+    metadata_cache::{self, CachedDocument},
+    npmrc::{Npmrc, NpmrcError, expand_env, registry_prefix},
+    ranking::rank_registries,
+    signature::{self, SignatureError},
+    tarball_cache::TarballCache,
+    utils::{CancellationToken, ClientExt, DownloadObserver, ReqwestExtError, ResultExt, Target},
 };
-use bytes::Bytes;
-use reqwest::{Client, ClientBuilder, header, header::HeaderMap};
-use semver::Version;
-use serde::Deserialize;
-use serde_with::{DisplayFromStr, Map, serde_as};
-use ssri::Integrity;
-use std::sync::LazyLock;
+use reqwest::{
+    Client, ClientBuilder, StatusCode, header,
+    header::{HeaderMap, HeaderValue},
+};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_with::{DefaultOnError, DisplayFromStr, Map, serde_as};
+use ssri::{Integrity, IntegrityChecker};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+use tokio::sync::OnceCell;
 use url::Url;
 
+/// `Accept` of the abbreviated package document, with the full document as fallback.
+const ACCEPT_ABBREVIATED: &str =
+    "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
+const ACCEPT_JSON: &str = "application/json; q=1, */*";
+/// Name of the cached registry keys, like their path no package can have.
+const KEYS_DOCUMENT: &str = "-/npm/v1/keys";
+
 #[derive(Debug)]
 pub struct NpmClient<'a> {
+    client: Client,
+    registries: &'a [Url],
+    scopes: BTreeMap<String, Url>,
+    tokens: Tokens,
+    network: NetworkConfig,
+    selection: RegistrySelection,
+    signatures: SignatureConfig,
+    tarball_cache: TarballCacheConfig,
+    cache_dir: Option<PathBuf>,
+    cache_only: bool,
+    ranked: OnceCell<Vec<Url>>,
+    keys: OnceCell<Vec<RegistryKey>>,
+}
+
+/// Builder for an [`NpmClient`] with a caller provided HTTP client or network settings.
+#[derive(Debug)]
+pub struct NpmClientBuilder<'a> {
+    client: Option<Client>,
     registries: &'a [Url],
+    scopes: BTreeMap<String, Url>,
+    tokens: Tokens,
+    network: NetworkConfig,
+    selection: RegistrySelection,
+    signatures: SignatureConfig,
+    tarball_cache: TarballCacheConfig,
+}
+
+/// Bearer tokens keyed by registry prefix, see [`registry_prefix`], kept out of debug output.
+#[derive(Default)]
+struct Tokens(BTreeMap<String, String>);
+
+impl Tokens {
+    /// Token of the longest registry prefix `url` starts with.
+    fn for_url(&self, url: &Url) -> Option<&str> {
+        let prefix = registry_prefix(url);
+        self.0
+            .iter()
+            .filter(|(registry, _)| prefix.starts_with(registry.as_str()))
+            .max_by_key(|(registry, _)| registry.len())
+            .map(|(_, token)| token.as_str())
+    }
+}
+
+impl fmt::Debug for Tokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum NpmError {
     #[error(transparent)]
     Http(#[from] ReqwestExtError),
+    #[error(transparent)]
+    Npmrc(#[from] NpmrcError),
+    #[error("invalid npm metadata: {0}")]
+    Metadata(#[from] serde_json::Error),
+    #[error("registry {0} must use HTTPS")]
+    InsecureRegistry(Url),
     #[error("npm metadata missing latest version")]
     MissingLatestVersion,
+    #[error("npm metadata has no version tagged {0}")]
+    UnknownDistTag(String),
+    #[error("no published version matches {0}")]
+    NoMatchingVersion(VersionReq),
     #[error("downloaded file integrity check failed: {0}")]
     Integrity(#[from] ssri::Error),
+    #[error("{0} failed verification: {1}")]
+    Signature(String, #[source] SignatureError),
+    #[error("{0} is not in the cache")]
+    NotCached(String),
 }
 
+/// A package document as served by the registry.
+///
+/// The abbreviated document, see [`NpmClient::get_abbreviated_packument`], has no publish times
+/// and only the install related fields of each version.
 #[serde_as]
-#[derive(Deserialize)]
-struct Metadata {
-    #[serde(rename = "dist-tags")]
-    dist_tags: DistTags,
+#[derive(Debug, Clone, Deserialize)]
+pub struct Packument {
+    pub name: String,
+    #[serde(rename = "dist-tags", default)]
+    pub dist_tags: BTreeMap<String, Version>,
     #[serde_as(as = "Map<_, _>")]
-    versions: Vec<(Version, VersionMetadata)>,
+    pub versions: Vec<(Version, VersionMetadata)>,
+    /// Publish time of each version, next to `created` and `modified`. Unpublished packages keep
+    /// an object here, which is skipped.
+    #[serde_as(as = "BTreeMap<_, DefaultOnError>")]
+    #[serde(default)]
+    time: BTreeMap<String, Option<String>>,
+}
+
+/// A published version with its history, as listed by [`NpmClient::list_versions`].
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version: Version,
+    /// Publish time in RFC 3339.
+    pub published: Option<String>,
+    /// Deprecation message, if the version has been deprecated.
+    pub deprecated: Option<String>,
+    /// Dist-tags pointing at this version.
+    pub dist_tags: Vec<String>,
+}
+
+impl Packument {
+    /// Pick the version `spec` refers to.
+    ///
+    /// Like npm, a range prefers the `latest` version if it satisfies the range, and the highest
+    /// matching version otherwise.
+    pub fn resolve(&self, spec: &VersionSpec) -> Result<&VersionMetadata, NpmError> {
+        let version = match spec {
+            VersionSpec::Tag(tag) => self.dist_tags.get(tag),
+            VersionSpec::Range(req) => self
+                .dist_tags
+                .get("latest")
+                .filter(|latest| req.matches(latest))
+                .or_else(|| {
+                    self.versions
+                        .iter()
+                        .map(|(version, _)| version)
+                        .filter(|version| req.matches(version))
+                        .max()
+                }),
+        };
+        version
+            .and_then(|version| self.version(version))
+            .ok_or_else(|| match spec {
+                VersionSpec::Tag(tag) if tag == "latest" => NpmError::MissingLatestVersion,
+                VersionSpec::Tag(tag) => NpmError::UnknownDistTag(tag.clone()),
+                VersionSpec::Range(req) => NpmError::NoMatchingVersion(req.clone()),
+            })
+    }
+
+    /// Metadata of an exact version.
+    pub fn version(&self, version: &Version) -> Option<&VersionMetadata> {
+        self.versions
+            .iter()
+            .find(|(v, _)| v == version)
+            .map(|(_, meta)| meta)
+    }
+
+    /// Publish time of `version` in RFC 3339.
+    pub fn published(&self, version: &Version) -> Option<&str> {
+        self.time.get(&version.to_string())?.as_deref()
+    }
+
+    /// All versions, newest first.
+    pub fn releases(&self) -> Vec<Release> {
+        let mut releases = self
+            .versions
+            .iter()
+            .map(|(version, meta)| Release {
+                version: version.clone(),
+                published: self.published(version).map(str::to_string),
+                deprecated: meta.deprecated.clone(),
+                dist_tags: self
+                    .dist_tags
+                    .iter()
+                    .filter(|(_, tagged)| *tagged == version)
+                    .map(|(tag, _)| tag.clone())
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        releases.sort_by(|a, b| b.version.cmp(&a.version));
+        releases
+    }
+}
+
+/// Which version of a package to resolve: a dist-tag such as `beta`, or a semver range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSpec {
+    Tag(String),
+    Range(VersionReq),
+}
+
+impl VersionSpec {
+    /// The `latest` dist-tag.
+    pub fn latest() -> Self {
+        VersionSpec::Tag("latest".to_string())
+    }
+
+    /// Exactly `version`.
+    pub fn exact(version: &Version) -> Self {
+        VersionSpec::Range(VersionReq {
+            comparators: vec![semver::Comparator {
+                op: semver::Op::Exact,
+                major: version.major,
+                minor: Some(version.minor),
+                patch: Some(version.patch),
+                pre: version.pre.clone(),
+            }],
+        })
+    }
+}
+
+impl Default for VersionSpec {
+    fn default() -> Self {
+        VersionSpec::latest()
+    }
+}
+
+impl FromStr for VersionSpec {
+    type Err = semver::Error;
+
+    /// Parse `1.4.2` as that exact version (as npm does), `^1.4` or `>=1, <2` as a range, and
+    /// anything starting with a letter as a dist-tag.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Ok(VersionSpec::Tag(s.to_string()));
+        }
+        if let Ok(version) = Version::parse(s) {
+            return Ok(VersionSpec::exact(&version));
+        }
+        VersionReq::parse(s).map(VersionSpec::Range)
+    }
 }
 
-#[derive(Deserialize)]
-struct DistTags {
-    latest: Version,
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSpec::Tag(tag) => f.write_str(tag),
+            VersionSpec::Range(req) => write!(f, "{req}"),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
 pub struct VersionMetadata {
     pub version: Version,
+    pub description: Option<String>,
     #[serde(rename = "githubTag")]
     pub github_tag: Option<String>,
+    /// Deprecation message, if the version has been deprecated.
+    #[serde_as(as = "DefaultOnError")]
+    #[serde(default)]
+    pub deprecated: Option<String>,
     pub dist: DistInfo,
 }
 
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DistInfo {
     #[serde_as(as = "DisplayFromStr")]
     integrity: Integrity,
     tarball: Url,
+    #[serde(rename = "fileCount")]
+    file_count: Option<u64>,
+    #[serde(rename = "unpackedSize")]
+    unpacked_size: Option<u64>,
+    #[serde(default)]
+    signatures: Vec<DistSignature>,
+}
+
+/// Registry signature over `<package>@<version>:<integrity>`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DistSignature {
+    pub keyid: String,
+    pub sig: String,
+}
+
+impl DistInfo {
+    pub fn integrity(&self) -> &Integrity {
+        &self.integrity
+    }
+
+    pub fn tarball(&self) -> &Url {
+        &self.tarball
+    }
+
+    /// Number of files in the tarball.
+    pub fn file_count(&self) -> Option<u64> {
+        self.file_count
+    }
+
+    /// Size of the extracted package in bytes.
+    pub fn unpacked_size(&self) -> Option<u64> {
+        self.unpacked_size
+    }
+
+    pub fn signatures(&self) -> &[DistSignature] {
+        &self.signatures
+    }
+
+    /// File name of the tarball, as published in the registry.
+    pub fn file_name(&self) -> &str {
+        self.tarball
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("package.tgz")
+    }
+
+    /// Tarball URLs rewritten onto each registry, in order, followed by the published URL.
+    ///
+    /// Registries lay tarballs out as `<package>/-/<file>`, with scoped packages taking two path
+    /// segments. A tarball URL not following this convention is only tried as published.
+    pub fn tarball_urls(&self, registries: &[Url]) -> Vec<Url> {
+        let mut urls = Vec::with_capacity(registries.len() + 1);
+        if let Some(path) = package_tarball_path(&self.tarball) {
+            urls.extend(
+                registries
+                    .iter()
+                    .filter_map(|base_url| base_url.join(&path).ok()),
+            );
+        }
+        if !urls.contains(&self.tarball) {
+            urls.push(self.tarball.clone());
+        }
+        urls
+    }
+}
+
+/// Scope of the package a tarball belongs to, e.g. `@lightsing`.
+fn tarball_scope(tarball: &Url) -> Option<String> {
+    package_tarball_path(tarball)?
+        .split('/')
+        .next()
+        .filter(|segment| segment.starts_with('@'))
+        .map(str::to_string)
+}
+
+/// Path of a tarball relative to its registry, e.g. `@scope/name/-/name-1.0.0.tgz`.
+fn package_tarball_path(tarball: &Url) -> Option<String> {
+    let segments = tarball.path_segments()?.collect::<Vec<_>>();
+    let separator = segments.iter().position(|segment| *segment == "-")?;
+    if segments.len() != separator + 2 || segments[separator + 1].is_empty() {
+        return None;
+    }
+    let package_start = match separator.checked_sub(2) {
+        Some(scope) if segments[scope].starts_with('@') => scope,
+        _ => separator.checked_sub(1)?,
+    };
+    Some(segments[package_start..].join("/"))
+}
+
+/// [`NpmError::Integrity`] if every mirror served a tarball failing the integrity check, so that
+/// bad bytes aren't mistaken for an unreachable network.
+fn integrity_error(e: ReqwestExtError) -> NpmError {
+    let ReqwestExtError::Mirrors(errors) = e else {
+        return e.into();
+    };
+    let integrity = errors
+        .iter()
+        .map(|mirror| match &mirror.error {
+            ReqwestExtError::Rejected(e) => e.downcast_ref::<ssri::Error>(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .and_then(|rejected| rejected.last().copied().cloned());
+    match integrity {
+        Some(e) => NpmError::Integrity(e),
+        None => ReqwestExtError::Mirrors(errors).into(),
+    }
+}
+
+/// Feeds the downloaded content through an [`IntegrityChecker`] while forwarding progress.
+struct IntegrityObserver<O> {
+    integrity: Integrity,
+    checker: IntegrityChecker,
+    inner: O,
+}
+
+impl<O> IntegrityObserver<O> {
+    fn new(integrity: Integrity, inner: O) -> Self {
+        IntegrityObserver {
+            checker: IntegrityChecker::new(integrity.clone()),
+            integrity,
+            inner,
+        }
+    }
+}
+
+impl<O: DownloadObserver> DownloadObserver for IntegrityObserver<O> {
+    fn on_progress(&mut self, received: u64, total: Option<u64>) {
+        self.inner.on_progress(received, total);
+    }
+
+    fn on_chunk(&mut self, chunk: &[u8]) {
+        self.checker.input(chunk);
+        self.inner.on_chunk(chunk);
+    }
+
+    fn on_restart(&mut self) {
+        self.checker = IntegrityChecker::new(self.integrity.clone());
+        self.inner.on_restart();
+    }
+
+    fn verify(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let checker = std::mem::replace(
+            &mut self.checker,
+            IntegrityChecker::new(self.integrity.clone()),
+        );
+        checker.result()?;
+        self.inner.verify()
+    }
 }
 
 impl<'a> NpmClient<'a> {
-    /// Create a new NpmClient with the given registries.
+    /// Create a new NpmClient with the given registries and default network settings.
     pub fn new(registries: &'a [Url]) -> Self {
-        NpmClient { registries }
+        NpmClient {
+            client: NPM_CLIENT.clone(),
+            registries,
+            scopes: BTreeMap::new(),
+            tokens: Tokens::default(),
+            network: NetworkConfig::default(),
+            selection: RegistrySelection::default(),
+            signatures: SignatureConfig::default(),
+            tarball_cache: TarballCacheConfig::default(),
+            cache_dir: None,
+            cache_only: false,
+            ranked: OnceCell::new(),
+            keys: OnceCell::new(),
+        }
+    }
+
+    /// Create a new NpmClient with the registries, scopes, tokens, network and signature
+    /// settings of `config`.
+    pub fn from_config(config: &'a LLCConfig) -> Result<Self, NpmError> {
+        let mut builder = NpmClient::builder(config.npm_registries())
+            .network(config.network().clone())
+            .registry_selection(config.registry_selection().clone())
+            .signatures(config.signatures().clone())
+            .tarball_cache(config.tarball_cache().clone());
+        for (scope, registry) in config.scopes() {
+            builder = builder.scope(scope, registry.clone());
+        }
+        for auth in config.registry_auth() {
+            let token = expand_env(auth.token(), |name| std::env::var(name).ok())?;
+            builder = builder.auth_token(auth.registry(), token);
+        }
+        if let Some(path) = config.npmrc() {
+            let npmrc = Npmrc::load(path)
+                .inspect_err(|e| error!("error loading npmrc {}: {e}", path.display()))?;
+            builder = builder.npmrc(&npmrc);
+        }
+        builder.build()
+    }
+
+    /// Start building a client for `registries`, see [`NpmClientBuilder`].
+    pub fn builder(registries: &'a [Url]) -> NpmClientBuilder<'a> {
+        NpmClientBuilder {
+            client: None,
+            registries,
+            scopes: BTreeMap::new(),
+            tokens: Tokens::default(),
+            network: NetworkConfig::default(),
+            selection: RegistrySelection::default(),
+            signatures: SignatureConfig::default(),
+            tarball_cache: TarballCacheConfig::default(),
+        }
+    }
+
+    /// Persist the registry ranking, package documents and downloaded tarballs in `dir`, so that
+    /// they are not fetched again on every start.
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
     }
 
-    /// Download a distribution file.
-    pub async fn download_dist(&self, dist: DistInfo) -> Result<Bytes, NpmError> {
-        let bytes = NPM_CLIENT
-            .download([dist.tarball].into_iter())
+    /// Never contact a registry if `cache_only` is set, e.g. in offline mode.
+    ///
+    /// Package documents and tarballs are served from the cache directory as they are, anything
+    /// not cached fails with [`NpmError::NotCached`].
+    pub fn cache_only(mut self, cache_only: bool) -> Self {
+        self.cache_only = cache_only;
+        self
+    }
+
+    /// Registries in the order they should be tried.
+    ///
+    /// With [`SelectionStrategy::Fastest`] the registries are probed on first use.
+    pub async fn registries(&self) -> &[Url] {
+        match self.selection.strategy() {
+            SelectionStrategy::Ordered => self.registries,
+            SelectionStrategy::Fastest => {
+                self.ranked
+                    .get_or_init(|| {
+                        rank_registries(
+                            &self.client,
+                            self.registries,
+                            &self.selection,
+                            self.cache_dir.as_deref(),
+                        )
+                    })
+                    .await
+            }
+        }
+    }
+
+    /// Registries to query for `package`, only its scope registry if it has one.
+    pub async fn registries_for(&self, package: &str) -> &[Url] {
+        match self.scope_registry(package) {
+            Some(registry) => std::slice::from_ref(registry),
+            None => self.registries().await,
+        }
+    }
+
+    /// The registry of the scope of `package`, if one is configured.
+    fn scope_registry(&self, package: &str) -> Option<&Url> {
+        let scope = package
+            .split('/')
+            .next()
+            .filter(|scope| scope.starts_with('@'))?;
+        self.scopes.get(scope)
+    }
+
+    /// `url` with the token of its registry, if any.
+    fn target(&self, url: Url) -> Target {
+        match self.tokens.for_url(&url) {
+            Some(token) => Target::new(url).bearer(token),
+            None => Target::new(url),
+        }
+    }
+
+    /// Download a distribution file into `dir`, returning the path of the verified tarball.
+    pub async fn download_dist(&self, dist: &DistInfo, dir: &Path) -> Result<PathBuf, NpmError> {
+        self.download_dist_with_progress(dist, dir, |_, _| {}, &CancellationToken::new())
             .await
-            .inspect_err(|e| error!("error downloading dist file: {e}"))?;
+    }
 
-        dist.integrity.check(&bytes)?;
-        Ok(bytes)
+    /// Download a distribution file into `dir`, reporting progress to `observer` until `cancel`
+    /// fires.
+    ///
+    /// The integrity is checked while streaming, so the tarball never has to be held in memory
+    /// and only a verified file ends up at the returned path. The tarball is fetched from the
+    /// registries in order, see [`DistInfo::tarball_urls`], a mirror serving bad bytes is skipped.
+    ///
+    /// With a cache directory, a tarball downloaded before is copied from the cache instead.
+    pub async fn download_dist_with_progress<O: DownloadObserver>(
+        &self,
+        dist: &DistInfo,
+        dir: &Path,
+        mut observer: O,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf, NpmError> {
+        let dest = dir.join(dist.file_name());
+        let cache = self
+            .cache_dir
+            .as_deref()
+            .map(|cache_dir| TarballCache::new(cache_dir, self.tarball_cache.clone()));
+        if let Some(cache) = &cache
+            && let Some(size) = cache.get(&dist.integrity, &dest).await
+        {
+            info!("using cached tarball {}", dist.file_name());
+            observer.on_progress(size, Some(size));
+            return Ok(dest);
+        }
+        if self.cache_only {
+            return Err(NpmError::NotCached(dist.file_name().to_string()));
+        }
+        let observer = IntegrityObserver::new(dist.integrity.clone(), observer);
+        let registries = match tarball_scope(&dist.tarball) {
+            Some(scope) => self.registries_for(&scope).await,
+            None => self.registries().await,
+        };
+        let mut urls = dist.tarball_urls(registries);
+        urls.retain(|url| {
+            let permitted = self.network.permits(url);
+            if !permitted {
+                warn!("skipping insecure tarball url {url}");
+            }
+            permitted
+        });
+        self.client
+            .download_to_with_progress(
+                urls.into_iter().map(|url| self.target(url)),
+                &dest,
+                self.network.retry(),
+                observer,
+                cancel,
+            )
+            .await
+            .map_err(integrity_error)
+            .inspect_err(|e| error!("error downloading dist file: {e}"))?;
+        if let Some(cache) = &cache {
+            cache.insert(&dist.integrity, &dest).await;
+        }
+        Ok(dest)
     }
 
     pub async fn get_lastest_version(&self, package: &str) -> Result<VersionMetadata, NpmError> {
-        let metadata = NPM_CLIENT
-            .get_json::<_, Metadata>(
-                self.registries
-                    .iter()
-                    .map(|base_url| base_url.join(package).infallible()),
+        self.resolve(package, &VersionSpec::latest()).await
+    }
+
+    /// Resolve the version of `package` matching `spec`, see [`VersionSpec`].
+    ///
+    /// The version is picked from the abbreviated document, its full manifest is fetched once
+    /// and cached for good, as published versions don't change. The manifest is checked with
+    /// [`NpmClient::verify`].
+    pub async fn resolve(
+        &self,
+        package: &str,
+        spec: &VersionSpec,
+    ) -> Result<VersionMetadata, NpmError> {
+        let packument = self.get_abbreviated_packument(package).await?;
+        let resolved = packument
+            .resolve(spec)
+            .inspect_err(|e| error!("error resolving {package}@{spec}: {e}"))?;
+        let mut manifest = self
+            .get_version_manifest(package, &resolved.version)
+            .await?;
+        // deprecation is the one thing that changes after publishing
+        manifest.deprecated = resolved.deprecated.clone();
+        self.verify(package, &manifest).await?;
+        Ok(manifest)
+    }
+
+    /// Check the registry signatures of a version of `package`.
+    ///
+    /// Does nothing if verification is disabled in the [`SignatureConfig`], or if `package` comes
+    /// from a scope registry the registry keys don't cover, as private registries don't sign.
+    /// A version served without signatures is checked with those of the registry of the keys.
+    /// Fails with [`NpmError::Http`] if the keys or signatures are needed but can't be fetched.
+    pub async fn verify(&self, package: &str, meta: &VersionMetadata) -> Result<(), NpmError> {
+        if !self.signatures.verify() {
+            return Ok(());
+        }
+        if let Some(registry) = self.scope_registry(package)
+            && !self.signatures.covers(registry)
+        {
+            debug!("not verifying {package}, {registry} isn't signed with the registry keys");
+            return Ok(());
+        }
+        let signed;
+        let meta = if meta.dist.signatures.is_empty() {
+            signed = self.with_origin_signatures(package, meta).await?;
+            &signed
+        } else {
+            meta
+        };
+        let keys = self.registry_keys(meta).await?;
+        signature::verify_signatures(package, meta, keys)
+            .map_err(|e| NpmError::Signature(format!("{package}@{}", meta.version), e))
+            .inspect_err(|e| error!("{e}"))
+    }
+
+    /// `meta` with the signatures of the registry of the keys, for mirrors serving versions without
+    /// them. They only verify if the mirror serves the integrity the registry signed.
+    async fn with_origin_signatures(
+        &self,
+        package: &str,
+        meta: &VersionMetadata,
+    ) -> Result<VersionMetadata, NpmError> {
+        // the keys are at `<registry>/-/npm/v1/keys`
+        let origin = self.signatures.keys_url().join("../../../").infallible();
+        if !self.network.permits(&origin) {
+            return Err(NpmError::InsecureRegistry(origin));
+        }
+        debug!(
+            "fetching the signatures of {package}@{} from {origin}",
+            meta.version
+        );
+        let version = meta.version.to_string();
+        let origin_meta: VersionMetadata = self
+            .fetch_document(
+                &format!("{package}/{version}"),
+                &format!(
+                    "{package}@{version} from {}",
+                    origin.host_str().unwrap_or_default()
+                ),
+                ACCEPT_JSON,
+                false,
+                Some(&origin),
             )
+            .await?;
+        let mut signed = meta.clone();
+        signed.dist.signatures = origin_meta.dist.signatures;
+        Ok(signed)
+    }
+
+    /// The pinned registry keys, else the built-in keys of registry.npmjs.org if they know every
+    /// signature of `meta`, else the keys fetched on first use.
+    async fn registry_keys(&self, meta: &VersionMetadata) -> Result<&[RegistryKey], NpmError> {
+        if !self.signatures.keys().is_empty() {
+            return Ok(self.signatures.keys());
+        }
+        let known = meta.dist.signatures().iter().all(|signature| {
+            NPM_REGISTRY_KEYS
+                .iter()
+                .any(|key| key.keyid() == signature.keyid)
+        });
+        if known {
+            return Ok(&NPM_REGISTRY_KEYS);
+        }
+        #[derive(Deserialize)]
+        struct Keys {
+            keys: Vec<RegistryKey>,
+        }
+        let keys = self
+            .keys
+            .get_or_try_init(|| async {
+                // the keys last fetched, so that cached versions can be verified offline
+                let cache_path = self
+                    .cache_dir
+                    .as_deref()
+                    .map(|dir| metadata_cache::document_path(dir, KEYS_DOCUMENT));
+                if self.cache_only {
+                    let document = match &cache_path {
+                        Some(cache_path) => metadata_cache::load(cache_path).await,
+                        None => None,
+                    }
+                    .ok_or_else(|| NpmError::NotCached("registry keys".to_string()))?;
+                    return Ok(serde_json::from_value::<Keys>(document.body)?.keys);
+                }
+                let url = self.signatures.keys_url();
+                if !self.network.permits(url) {
+                    return Err(NpmError::InsecureRegistry(url.clone()));
+                }
+                let body = self
+                    .client
+                    .get_json::<_, serde_json::Value>(
+                        std::iter::once(url.clone()),
+                        self.network.retry(),
+                    )
+                    .await
+                    .inspect_err(|e| error!("error fetching registry keys: {e}"))?;
+                let keys = serde_json::from_value::<Keys>(body.clone())?.keys;
+                if let Some(cache_path) = &cache_path {
+                    let document = CachedDocument {
+                        url: url.clone(),
+                        etag: None,
+                        body,
+                    };
+                    metadata_cache::store(cache_path, &document).await;
+                }
+                Ok(keys)
+            })
+            .await?;
+        Ok(keys)
+    }
+
+    /// All published versions of `package`, newest first.
+    pub async fn list_versions(&self, package: &str) -> Result<Vec<Release>, NpmError> {
+        Ok(self.get_packument(package).await?.releases())
+    }
+
+    /// Fetch the full package document of `package`.
+    pub async fn get_packument(&self, package: &str) -> Result<Packument, NpmError> {
+        self.fetch_document(package, package, ACCEPT_JSON, true, None)
+            .await
+    }
+
+    /// Fetch the abbreviated package document of `package`, much smaller than the full one.
+    ///
+    /// Registries without abbreviated documents send the full one instead.
+    pub async fn get_abbreviated_packument(&self, package: &str) -> Result<Packument, NpmError> {
+        self.fetch_document(
+            package,
+            &format!("{package}.install-v1"),
+            ACCEPT_ABBREVIATED,
+            true,
+            None,
+        )
+        .await
+    }
+
+    /// Fetch the manifest of one published version of `package`.
+    pub async fn get_version_manifest(
+        &self,
+        package: &str,
+        version: &Version,
+    ) -> Result<VersionMetadata, NpmError> {
+        let path = format!("{package}/{version}");
+        self.fetch_document(
+            &path,
+            &format!("{package}@{version}"),
+            ACCEPT_JSON,
+            false,
+            None,
+        )
+        .await
+    }
+
+    /// Fetch `path` from `registry`, else from the registries of its package.
+    ///
+    /// With a cache directory the document is stored as `name` and, when `revalidate` is set,
+    /// fetched again with `If-None-Match`, a `304 Not Modified` answer reuses the stored copy.
+    async fn fetch_document<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        name: &str,
+        accept: &'static str,
+        revalidate: bool,
+        registry: Option<&Url>,
+    ) -> Result<T, NpmError> {
+        let cache_path = self
+            .cache_dir
+            .as_deref()
+            .map(|dir| metadata_cache::document_path(dir, name));
+        let mut cached = None;
+        if let Some(cache_path) = &cache_path
+            && let Some(document) = metadata_cache::load(cache_path).await
+        {
+            match serde_json::from_value::<T>(document.body.clone()) {
+                Ok(value) if !revalidate || self.cache_only => return Ok(value),
+                Ok(value) => cached = Some((document, value)),
+                Err(e) => warn!("ignoring invalid cached document {name}: {e}"),
+            }
+        }
+        if self.cache_only {
+            return Err(NpmError::NotCached(name.to_string()));
+        }
+
+        let validator = cached.as_ref().and_then(|(document, _)| {
+            let etag = HeaderValue::from_str(document.etag.as_ref()?).ok()?;
+            Some((document.url.clone(), etag))
+        });
+        let registries = match registry {
+            Some(registry) => std::slice::from_ref(registry),
+            None => self.registries_for(path).await,
+        };
+        let targets = registries.iter().map(|base_url| {
+            let url = base_url.join(path).infallible();
+            let etag = validator
+                .as_ref()
+                .filter(|(cached_url, _)| *cached_url == url)
+                .map(|(_, etag)| etag.clone());
+            let target = self
+                .target(url)
+                .header(header::ACCEPT, HeaderValue::from_static(accept));
+            match etag {
+                Some(etag) => target.header(header::IF_NONE_MATCH, etag),
+                None => target,
+            }
+        });
+        let response = self
+            .client
+            .try_get(targets, self.network.retry())
             .await
             .inspect_err(|e| error!("error fetching npm metadata: {e}"))?;
+        if response.status() == StatusCode::NOT_MODIFIED
+            && let Some((_, value)) = cached
+        {
+            debug!("npm metadata of {name} not modified");
+            return Ok(value);
+        }
 
-        metadata
-            .versions
-            .into_iter()
-            .find(|(version, _)| version == &metadata.dist_tags.latest)
-            .ok_or(NpmError::MissingLatestVersion)
-            .map(|(_, meta)| meta)
-            .inspect_err(|e| error!("error finding latest version in metadata: {e}"))
+        let url = response.url().clone();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(ReqwestExtError::from)
+            .inspect_err(|e| error!("error reading npm metadata: {e}"))?;
+        let value = serde_json::from_value(body.clone())
+            .inspect_err(|e| error!("error parsing npm metadata of {name}: {e}"))?;
+        if let Some(cache_path) = &cache_path {
+            metadata_cache::store(cache_path, &CachedDocument { url, etag, body }).await;
+        }
+        Ok(value)
     }
 }
 
-static NPM_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+impl<'a> NpmClientBuilder<'a> {
+    /// Use `client` for all requests instead of building one.
+    ///
+    /// Timeouts, proxies and TLS roots are then up to the caller, [`npm_client_builder`] is a
+    /// good starting point. The retry policy of the network settings still applies.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Set the network settings, used to build the HTTP client unless one is provided.
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Query only `registry` for packages of `scope`, e.g. `@lightsing`.
+    pub fn scope(mut self, scope: &str, registry: Url) -> Self {
+        self.scopes.insert(scope.to_string(), registry);
+        self
+    }
+
+    /// Send `token` as bearer token with every request under `registry`.
+    pub fn auth_token(mut self, registry: &Url, token: impl Into<String>) -> Self {
+        self.tokens
+            .0
+            .insert(registry_prefix(registry), token.into());
+        self
+    }
+
+    /// Add the scopes and tokens of `npmrc` not set yet.
+    pub fn npmrc(mut self, npmrc: &Npmrc) -> Self {
+        for (scope, registry) in npmrc.scopes() {
+            self.scopes
+                .entry(scope.clone())
+                .or_insert_with(|| registry.clone());
+        }
+        for (prefix, token) in npmrc.tokens() {
+            self.tokens
+                .0
+                .entry(prefix.clone())
+                .or_insert_with(|| token.clone());
+        }
+        self
+    }
+
+    /// Set how the registries are ordered.
+    pub fn registry_selection(mut self, selection: RegistrySelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Set how published versions are verified.
+    pub fn signatures(mut self, signatures: SignatureConfig) -> Self {
+        self.signatures = signatures;
+        self
+    }
+
+    /// Set the limits of the tarball cache, used once a cache directory is set.
+    pub fn tarball_cache(mut self, tarball_cache: TarballCacheConfig) -> Self {
+        self.tarball_cache = tarball_cache;
+        self
+    }
+
+    pub fn build(self) -> Result<NpmClient<'a>, NpmError> {
+        if let Some(registry) = self
+            .registries
+            .iter()
+            .chain(self.scopes.values())
+            .find(|url| !self.network.permits(url))
+        {
+            return Err(NpmError::InsecureRegistry(registry.clone()));
+        }
+        let client = match self.client {
+            Some(client) => client,
+            None => self
+                .network
+                .apply(npm_client_builder())
+                .and_then(ClientBuilder::build)
+                .map_err(ReqwestExtError::from)?,
+        };
+        Ok(NpmClient {
+            client,
+            registries: self.registries,
+            scopes: self.scopes,
+            tokens: self.tokens,
+            network: self.network,
+            selection: self.selection,
+            signatures: self.signatures,
+            tarball_cache: self.tarball_cache,
+            cache_dir: None,
+            cache_only: false,
+            ranked: OnceCell::new(),
+            keys: OnceCell::new(),
+        })
+    }
+}
+
+/// Client builder with the user agent and headers npm registries are queried with.
+pub fn npm_client_builder() -> ClientBuilder {
     ClientBuilder::default()
         .user_agent(*USER_AGENT)
         .default_headers(HeaderMap::from_iter([
             (header::FROM, "ligh.tsing@gmail.com".parse().infallible()),
-            (
-                header::ACCEPT,
-                "application/json; q=1, */*".parse().infallible(),
-            ),
+            (header::ACCEPT, HeaderValue::from_static(ACCEPT_JSON)),
         ]))
-        .build()
+}
+
+static NPM_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    NetworkConfig::default()
+        .apply(npm_client_builder())
+        .and_then(ClientBuilder::build)
         .expect("Failed to build default NPM client")
 });
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{MockRegistry, Package, TempDir, mock_config, mock_public_key};
     use tokio::test;
 
+    const PKG_NAME: &str = "@lightsing/llc-zh-cn";
+
     #[test]
     async fn test_get_npm_metadata() {
-        let registries = crate::config::default_npm_registries();
-        let npm_client = NpmClient::new(&registries);
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "1.0.0", *b"old").github_tag("v1.0.0"));
+        registry.publish(Package::new(PKG_NAME, "1.2.0", *b"new").github_tag("v1.2.0"));
+        // a flaky registry is retried
+        registry.fail_next(1, 503);
+
+        let config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+        let ver = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        assert_eq!(ver.version, Version::new(1, 2, 0));
+        assert_eq!(ver.github_tag.as_deref(), Some("v1.2.0"));
+    }
+
+    #[test]
+    async fn test_resolve() {
+        let registry = MockRegistry::start().await;
+        for version in ["1.0.0", "1.4.0", "1.5.0", "2.0.0", "2.1.0-beta.1"] {
+            registry.publish(Package::new(PKG_NAME, version, *b""));
+        }
+        registry.tag(PKG_NAME, "beta", "2.1.0-beta.1");
+        registry.tag(PKG_NAME, "latest", "1.5.0");
+        let config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+
+        let resolve = async |spec: &str| {
+            npm_client
+                .resolve(PKG_NAME, &spec.parse().unwrap())
+                .await
+                .map(|meta| meta.version.to_string())
+        };
+        assert_eq!(resolve("latest").await.unwrap(), "1.5.0");
+        assert_eq!(resolve("beta").await.unwrap(), "2.1.0-beta.1");
+        assert_eq!(resolve("^1.4").await.unwrap(), "1.5.0");
+        assert_eq!(resolve("~1.4").await.unwrap(), "1.4.0");
+        assert_eq!(resolve("1.0.0").await.unwrap(), "1.0.0");
+        // latest is preferred, but not required
+        assert_eq!(resolve(">=1").await.unwrap(), "1.5.0");
+        assert_eq!(resolve(">=2").await.unwrap(), "2.0.0");
+        assert!(matches!(
+            resolve("next").await,
+            Err(NpmError::UnknownDistTag(tag)) if tag == "next"
+        ));
+        assert!(matches!(
+            resolve("^3").await,
+            Err(NpmError::NoMatchingVersion(_))
+        ));
+    }
+
+    #[test]
+    async fn test_list_versions() {
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "1.0.0", *b"").deprecated("broken fonts"));
+        registry.publish(Package::new(PKG_NAME, "1.1.0", *b"llc"));
+        let config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+
+        let releases = npm_client.list_versions(PKG_NAME).await.unwrap();
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].version, Version::new(1, 1, 0));
+        assert_eq!(releases[0].dist_tags, ["latest"]);
+        assert!(releases[0].published.is_some());
+        assert_eq!(releases[1].deprecated.as_deref(), Some("broken fonts"));
+
+        let packument = npm_client.get_packument(PKG_NAME).await.unwrap();
+        let dist = &packument.version(&Version::new(1, 1, 0)).unwrap().dist;
+        assert_eq!(dist.unpacked_size(), Some(3));
+    }
+
+    #[test]
+    async fn test_metadata_cache() {
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "1.0.0", *b"old").github_tag("v1.0.0"));
+        let config = mock_config(&[&registry]);
+        let cache_dir = TempDir::new("llc-rs-metadata-cache");
+        let npm_client = NpmClient::from_config(&config)
+            .unwrap()
+            .with_cache_dir(&*cache_dir);
+
+        let ver = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        assert_eq!(ver.github_tag.as_deref(), Some("v1.0.0"));
+        // abbreviated document, manifest and registry keys
+        let requests = registry.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(
+            requests[0]
+                .header("Accept")
+                .unwrap()
+                .starts_with("application/vnd.npm.install-v1+json")
+        );
+        assert!(requests[0].header("If-None-Match").is_none());
 
-        let ver = npm_client
-            .get_lastest_version("@lightsing/llc-launcher-rs-win32")
+        // unchanged: only the abbreviated document is revalidated, the manifest is kept
+        let ver = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        assert_eq!(ver.github_tag.as_deref(), Some("v1.0.0"));
+        let requests = registry.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[3].header("If-None-Match").is_some());
+
+        registry.publish(Package::new(PKG_NAME, "1.1.0", *b"new").github_tag("v1.1.0"));
+        let ver = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        assert_eq!(ver.github_tag.as_deref(), Some("v1.1.0"));
+        assert_eq!(registry.requests().len(), 6);
+    }
+
+    #[test]
+    async fn test_verify() {
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "1.1.0", *b"signed"));
+        registry.publish(Package::new(PKG_NAME, "1.2.0", *b"unsigned").unsigned());
+        let config = mock_config(&[&registry]);
+        let client = |signatures: &str| {
+            let keys_url = config.signatures().keys_url();
+            let signatures = format!("keys-url = \"{keys_url}\"\n{signatures}");
+            NpmClient::builder(config.npm_registries())
+                .network(config.network().clone())
+                .signatures(toml::from_str(&signatures).unwrap())
+                .build()
+                .unwrap()
+        };
+        let resolve = async |npm_client: &NpmClient<'_>, version: &str| {
+            npm_client
+                .resolve(PKG_NAME, &version.parse().unwrap())
+                .await
+                .map(|meta| meta.version.to_string())
+        };
+
+        let npm_client = client("");
+        assert_eq!(resolve(&npm_client, "1.1.0").await.unwrap(), "1.1.0");
+        assert!(matches!(
+            resolve(&npm_client, "1.2.0").await,
+            Err(NpmError::Signature(_, SignatureError::Missing))
+        ));
+        assert_eq!(
+            resolve(&client("verify = false"), "1.2.0").await.unwrap(),
+            "1.2.0"
+        );
+
+        // keys that can't be fetched are like an unreachable registry
+        let closed = MockRegistry::start()
             .await
+            .url()
+            .join("-/npm/v1/keys")
             .unwrap();
-        println!("[test_get_npm_metadata] Latest version: {ver:?}");
+        let npm_client = NpmClient::builder(config.npm_registries())
+            .network(config.network().clone())
+            .signatures(toml::from_str(&format!("keys-url = \"{closed}\"")).unwrap())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            resolve(&npm_client, "1.1.0").await,
+            Err(NpmError::Http(_))
+        ));
+
+        // pinned keys replace the keys of the registry
+        let npm_client = client(&format!(
+            "[[keys]]\nkeyid = \"SHA256:pinned\"\nkey = \"{}\"",
+            mock_public_key()
+        ));
+        assert!(matches!(
+            resolve(&npm_client, "1.1.0").await,
+            Err(NpmError::Signature(_, SignatureError::UnknownKey(_)))
+        ));
     }
 
     #[test]
     async fn test_download_dist() {
-        let registries = crate::config::default_npm_registries();
+        let registry = MockRegistry::start().await;
+        let tarball = vec![42; 64 * 1024];
+        registry.publish(Package::new(PKG_NAME, "1.0.0", tarball.clone()));
+        let config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+        let dir = TempDir::new("llc-rs-download-dist");
+
+        let meta = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        let path = npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+        assert_eq!(path, dir.join("llc-zh-cn-1.0.0.tgz"));
+        assert_eq!(std::fs::read(path).unwrap(), tarball);
+    }
+
+    #[test]
+    async fn test_verify_mirror_without_signatures() {
+        let mirror = MockRegistry::start().await;
+        let origin = MockRegistry::start().await;
+        mirror.publish(Package::new(PKG_NAME, "1.0.0", *b"genuine").unsigned());
+        mirror.publish(Package::new(PKG_NAME, "1.1.0", *b"forged").unsigned());
+        origin.publish(Package::new(PKG_NAME, "1.0.0", *b"genuine"));
+        origin.publish(Package::new(PKG_NAME, "1.1.0", *b"genuine"));
+        let config = mock_config(&[&mirror]);
+        let keys_url = origin.url().join("-/npm/v1/keys").unwrap();
+        let npm_client = NpmClient::builder(config.npm_registries())
+            .network(config.network().clone())
+            .signatures(toml::from_str(&format!("keys-url = \"{keys_url}\"")).unwrap())
+            .build()
+            .unwrap();
+
+        let meta = npm_client
+            .resolve(PKG_NAME, &VersionSpec::exact(&Version::new(1, 0, 0)))
+            .await
+            .unwrap();
+        assert!(meta.dist.signatures().is_empty());
+        // the signatures are for the integrity the registry published
+        assert!(matches!(
+            npm_client
+                .resolve(PKG_NAME, &VersionSpec::exact(&Version::new(1, 1, 0)))
+                .await,
+            Err(NpmError::Signature(_, SignatureError::Invalid(_)))
+        ));
+        // only the manifests were fetched from the registry
+        assert!(
+            origin
+                .requests()
+                .iter()
+                .all(|request| request.path.ends_with(".0") || request.path.ends_with("keys"))
+        );
+    }
+
+    /// Default installs verify the versions npmmirror serves with the signatures it keeps.
+    #[test]
+    #[ignore = "needs registry.npmmirror.com"]
+    async fn test_npmmirror_signatures() {
+        let registries = [Url::parse("https://registry.npmmirror.com").unwrap()];
         let npm_client = NpmClient::new(&registries);
+        let packument = npm_client
+            .get_abbreviated_packument(PKG_NAME)
+            .await
+            .unwrap();
+        let latest = &packument.dist_tags["latest"];
+        let meta = npm_client
+            .get_version_manifest(PKG_NAME, latest)
+            .await
+            .unwrap();
+        assert!(!meta.dist.signatures().is_empty());
+        signature::verify_signatures(PKG_NAME, &meta, &NPM_REGISTRY_KEYS).unwrap();
+    }
+
+    #[test]
+    async fn test_download_dist_cached() {
+        let registry = MockRegistry::start().await;
+        let tarball = vec![42; 64 * 1024];
+        let package = Package::new(PKG_NAME, "1.0.0", tarball.clone());
+        registry.publish(package.clone());
+        let config = mock_config(&[&registry]);
+        let dir = TempDir::new("llc-rs-download-dist-cached");
+        let npm_client = NpmClient::from_config(&config)
+            .unwrap()
+            .with_cache_dir(&*dir);
+        let tarball_requests = || {
+            registry
+                .requests()
+                .iter()
+                .filter(|request| request.path == registry.tarball_url(&package).path())
+                .count()
+        };
+
+        let meta = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        let path = npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(tarball_requests(), 1);
 
+        // the second download, e.g. after a reinstall, comes from the cache
+        let mut progress = None;
+        let path = npm_client
+            .download_dist_with_progress(
+                &meta.dist,
+                &dir,
+                |received, total| progress = Some((received, total)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), tarball);
+        assert_eq!(tarball_requests(), 1);
+        assert_eq!(progress, Some((64 * 1024, Some(64 * 1024))));
+    }
+
+    #[test]
+    async fn test_cache_only() {
+        let registry = MockRegistry::start().await;
+        let tarball = vec![42; 1024];
+        registry.publish(Package::new(PKG_NAME, "1.0.0", tarball.clone()));
+        registry.publish(Package::new(PKG_NAME, "1.1.0", vec![7; 1024]));
+        let config = mock_config(&[&registry]);
+        let dir = TempDir::new("llc-rs-cache-only");
+        let version = Version::new(1, 0, 0);
+        let npm_client = NpmClient::from_config(&config)
+            .unwrap()
+            .with_cache_dir(&*dir);
+        let meta = npm_client
+            .resolve(PKG_NAME, &VersionSpec::exact(&version))
+            .await
+            .unwrap();
+        std::fs::remove_file(npm_client.download_dist(&meta.dist, &dir).await.unwrap()).unwrap();
+        let requests = registry.requests().len();
+
+        let npm_client = NpmClient::from_config(&config)
+            .unwrap()
+            .with_cache_dir(&*dir)
+            .cache_only(true);
         let meta = npm_client
-            .get_lastest_version("@lightsing/llc-zh-cn")
+            .resolve(PKG_NAME, &VersionSpec::exact(&version))
+            .await
+            .unwrap();
+        let path = npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), tarball);
+        // 1.1.0 was never fetched
+        let err = npm_client
+            .resolve(PKG_NAME, &VersionSpec::latest())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, NpmError::NotCached(_)), "{err:?}");
+        assert_eq!(registry.requests().len(), requests);
+    }
+
+    #[test]
+    async fn test_download_dist_resume() {
+        let registry = MockRegistry::start().await;
+        let tarball = (0..64 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        registry.publish(Package::new(PKG_NAME, "1.0.0", tarball.clone()));
+        let config = mock_config(&[&registry]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+        let dir = TempDir::new("llc-rs-download-resume");
+
+        let meta = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        registry.truncate_next(20 * 1024);
+        let path = npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), tarball);
+
+        let ranges = registry
+            .requests()
+            .iter()
+            .filter_map(|request| request.header("Range").map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, ["bytes=20480-"]);
+    }
+
+    #[test]
+    async fn test_download_dist_bad_mirror() {
+        let bad = MockRegistry::start().await;
+        let good = MockRegistry::start().await;
+        for registry in [&bad, &good] {
+            registry.publish(Package::new(PKG_NAME, "1.0.0", *b"llc"));
+        }
+        bad.corrupt_tarballs(true);
+        let config = mock_config(&[&bad, &good]);
+        let npm_client = NpmClient::from_config(&config).unwrap();
+        let dir = TempDir::new("llc-rs-download-bad-mirror");
+
+        let meta = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        let path = npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"llc");
+
+        good.corrupt_tarballs(true);
+        let err = npm_client
+            .download_dist(&meta.dist, &dir)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, NpmError::Integrity(_)),
+            "unexpected error: {err}"
+        );
+
+        // a mirror failing otherwise is still a network error
+        good.fail_next(1, 404);
+        let err = npm_client
+            .download_dist(&meta.dist, &dir)
             .await
+            .unwrap_err();
+        let NpmError::Http(ReqwestExtError::Mirrors(errors)) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    async fn test_custom_client() {
+        let registry = MockRegistry::start().await;
+        registry.publish(Package::new(PKG_NAME, "1.0.0", *b"llc"));
+        let config = mock_config(&[&registry]);
+        let client = npm_client_builder()
+            .default_headers(HeaderMap::from_iter([(
+                header::AUTHORIZATION,
+                "Bearer test".parse().unwrap(),
+            )]))
+            .build()
+            .unwrap();
+        let npm_client = NpmClient::builder(config.npm_registries())
+            .client(client)
+            .network(config.network().clone())
+            .signatures(config.signatures().clone())
+            .build()
             .unwrap();
 
-        npm_client.download_dist(meta.dist).await.unwrap();
+        npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        assert_eq!(
+            registry.requests()[0].header("Authorization"),
+            Some("Bearer test")
+        );
+    }
+
+    #[test]
+    async fn test_scoped_registry() {
+        let public = MockRegistry::start().await;
+        let private = MockRegistry::start().await;
+        private.publish(Package::new(PKG_NAME, "1.0.0", *b"llc"));
+        let config = mock_config(&[&public]);
+        let npm_client = NpmClient::builder(config.npm_registries())
+            .network(config.network().clone())
+            .signatures(config.signatures().clone())
+            .scope("@lightsing", private.url().clone())
+            .auth_token(private.url(), "s3cret")
+            .build()
+            .unwrap();
+        let dir = TempDir::new("llc-rs-scoped-registry");
+
+        let meta = npm_client.get_lastest_version(PKG_NAME).await.unwrap();
+        npm_client.download_dist(&meta.dist, &dir).await.unwrap();
+
+        // private registries don't sign, nothing comes from the public registry
+        assert!(public.requests().is_empty());
+        let requests = private.requests();
+        assert_eq!(requests.len(), 3);
+        for request in requests {
+            assert_eq!(request.header("Authorization"), Some("Bearer s3cret"));
+        }
+    }
+
+    #[test]
+    async fn test_insecure_registry() {
+        let config =
+            LLCConfig::for_local_registries(vec![Url::parse("http://registry.npmjs.org").unwrap()]);
+        assert!(matches!(
+            NpmClient::from_config(&config),
+            Err(NpmError::InsecureRegistry(_))
+        ));
+    }
+
+    #[test]
+    async fn test_tarball_urls() {
+        let registries = [
+            Url::parse("https://registry.npmmirror.com").unwrap(),
+            Url::parse("https://registry.npmjs.org").unwrap(),
+            Url::parse("https://example.com/npm/").unwrap(),
+        ];
+        let dist = |tarball: &str| DistInfo {
+            integrity: Integrity::from(b""),
+            tarball: Url::parse(tarball).unwrap(),
+            file_count: None,
+            unpacked_size: None,
+            signatures: vec![],
+        };
+
+        let urls = dist("https://registry.npmjs.org/@lightsing/llc-zh-cn/-/llc-zh-cn-1.0.0.tgz")
+            .tarball_urls(&registries);
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://registry.npmmirror.com/@lightsing/llc-zh-cn/-/llc-zh-cn-1.0.0.tgz",
+                "https://registry.npmjs.org/@lightsing/llc-zh-cn/-/llc-zh-cn-1.0.0.tgz",
+                "https://example.com/npm/@lightsing/llc-zh-cn/-/llc-zh-cn-1.0.0.tgz",
+            ]
+        );
+
+        let urls = dist("https://cdn.example.com/npm/ssri/-/ssri-9.0.0.tgz")
+            .tarball_urls(&registries[..1]);
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://registry.npmmirror.com/ssri/-/ssri-9.0.0.tgz",
+                "https://cdn.example.com/npm/ssri/-/ssri-9.0.0.tgz",
+            ]
+        );
+
+        let urls = dist("https://cdn.example.com/files/llc.tgz").tarball_urls(&registries);
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            ["https://cdn.example.com/files/llc.tgz"]
+        );
+    }
+
+    #[test]
+    async fn test_integrity_observer() {
+        let integrity = Integrity::from(b"hello world");
+        let mut observer = IntegrityObserver::new(integrity, |_, _| {});
+        observer.on_chunk(b"hello ");
+        observer.on_restart();
+        observer.on_chunk(b"hello ");
+        observer.on_chunk(b"world");
+        assert!(observer.verify().is_ok());
+
+        observer.on_chunk(b"tampered");
+        assert!(observer.verify().is_err());
     }
 }
@@ -0,0 +1,164 @@
+//! The subset of `.npmrc` needed to reach private registries: scope registries and auth tokens.
+
+use std::{collections::BTreeMap, fmt, path::Path};
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NpmrcError {
+    #[error("failed to read npmrc: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("environment variable {0} referenced in npm config is not set")]
+    MissingEnv(String),
+    #[error("invalid registry url {0:?} in npmrc")]
+    InvalidUrl(String),
+}
+
+/// Registries and tokens read from an `.npmrc` file.
+///
+/// Only `@scope:registry=<url>` and `//<registry>/:_authToken=<token>` entries are used,
+/// everything else is ignored. `${VAR}` in values is replaced by the environment variable.
+#[derive(Default)]
+pub struct Npmrc {
+    scopes: BTreeMap<String, Url>,
+    tokens: BTreeMap<String, String>,
+}
+
+impl Npmrc {
+    pub fn load(path: &Path) -> Result<Self, NpmrcError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, NpmrcError> {
+        Self::parse_with_env(content, |name| std::env::var(name).ok())
+    }
+
+    fn parse_with_env(
+        content: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, NpmrcError> {
+        let mut npmrc = Npmrc::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), unquote(value.trim()));
+            if let Some(scope) = key.strip_suffix(":registry")
+                && scope.starts_with('@')
+            {
+                let value = expand_env(value, &env)?;
+                let url = Url::parse(&value).map_err(|_| NpmrcError::InvalidUrl(value))?;
+                npmrc.scopes.insert(scope.to_string(), url);
+            } else if let Some(prefix) = key.strip_suffix(":_authToken")
+                && prefix.starts_with("//")
+            {
+                npmrc
+                    .tokens
+                    .insert(prefix.to_string(), expand_env(value, &env)?);
+            }
+        }
+        Ok(npmrc)
+    }
+
+    /// Registry of each scope, keyed by `@scope`.
+    pub fn scopes(&self) -> &BTreeMap<String, Url> {
+        &self.scopes
+    }
+
+    /// Auth tokens keyed by registry prefix without scheme, e.g. `//npm.example.com/`.
+    pub fn tokens(&self) -> &BTreeMap<String, String> {
+        &self.tokens
+    }
+}
+
+impl fmt::Debug for Npmrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Npmrc")
+            .field("scopes", &self.scopes)
+            .field("tokens", &self.tokens.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Replace every `${VAR}` in `value` with the environment variable `VAR`.
+pub(crate) fn expand_env(
+    value: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<String, NpmrcError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&env(name).ok_or_else(|| NpmrcError::MissingEnv(name.to_string()))?);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Registry prefix in npm's form, the URL without scheme and with a trailing slash.
+pub(crate) fn registry_prefix(url: &Url) -> String {
+    let mut prefix = format!("//{}{}", url.authority(), url.path());
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_npmrc() {
+        let env = |name: &str| (name == "LLC_TOKEN").then(|| "s3cret".to_string());
+        let npmrc = Npmrc::parse_with_env(
+            r#"
+# private builds
+registry=https://registry.npmjs.org/
+@lightsing:registry=https://npm.example.com/private/
+//npm.example.com/private/:_authToken=${LLC_TOKEN}
+; quoted values are allowed
+//registry.npmjs.org/:_authToken="npm_abc"
+always-auth=true
+"#,
+            env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            npmrc.scopes()["@lightsing"].as_str(),
+            "https://npm.example.com/private/"
+        );
+        assert_eq!(npmrc.tokens()["//npm.example.com/private/"], "s3cret");
+        assert_eq!(npmrc.tokens()["//registry.npmjs.org/"], "npm_abc");
+
+        assert!(matches!(
+            Npmrc::parse_with_env("//npm.example.com/:_authToken=${MISSING}", env),
+            Err(NpmrcError::MissingEnv(name)) if name == "MISSING"
+        ));
+    }
+
+    #[test]
+    fn test_registry_prefix() {
+        let prefix = |url: &str| registry_prefix(&Url::parse(url).unwrap());
+        assert_eq!(prefix("https://npm.example.com"), "//npm.example.com/");
+        assert_eq!(
+            prefix("http://127.0.0.1:4873/private"),
+            "//127.0.0.1:4873/private/"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Latency based ordering of the NPM registries.
+
+use crate::{RegistrySelection, utils::ResultExt};
+use futures_util::future::join_all;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+/// File name of the ranking cache inside the cache directory.
+pub(crate) const RANKING_CACHE_FILE: &str = "registry-ranking.json";
+
+/// A ranking persisted on disk, reused until it expires.
+#[derive(Debug, Serialize, Deserialize)]
+struct RankingCache {
+    /// Unix timestamp of the probe, in seconds.
+    probed_at: u64,
+    ranked: Vec<Url>,
+}
+
+impl RankingCache {
+    /// Whether this ranking can be used for `registries` at `now`.
+    fn is_valid(&self, registries: &[Url], ttl: Duration, now: SystemTime) -> bool {
+        let fresh = unix_seconds(now)
+            .checked_sub(self.probed_at)
+            .is_some_and(|age| age < ttl.as_secs());
+        // a changed registry list invalidates the ranking
+        fresh
+            && self.ranked.len() == registries.len()
+            && registries.iter().all(|url| self.ranked.contains(url))
+    }
+}
+
+/// Order `registries` by their probe latency, fastest first.
+///
+/// The ranking is read from and written to `cache_dir` when given.
+pub(crate) async fn rank_registries(
+    client: &Client,
+    registries: &[Url],
+    selection: &RegistrySelection,
+    cache_dir: Option<&Path>,
+) -> Vec<Url> {
+    let cache_path = cache_dir.map(|dir| dir.join(RANKING_CACHE_FILE));
+    if let Some(cache) = cache_path.as_deref().and_then(load_cache)
+        && cache.is_valid(registries, selection.cache_ttl(), SystemTime::now())
+    {
+        debug!("using cached registry ranking: {:?}", cache.ranked);
+        return cache.ranked;
+    }
+
+    let latencies = join_all(
+        registries
+            .iter()
+            .map(|url| probe(client, url, selection.probe_timeout())),
+    )
+    .await;
+    let ranked = order_by_latency(registries, &latencies);
+    info!("registry ranking: {ranked:?}");
+
+    if let Some(cache_path) = cache_path {
+        let cache = RankingCache {
+            probed_at: unix_seconds(SystemTime::now()),
+            ranked,
+        };
+        tokio::fs::write(&cache_path, serde_json::to_vec(&cache).infallible())
+            .await
+            .inspect_err(|e| warn!("failed to save registry ranking: {e}"))
+            .ok();
+        return cache.ranked;
+    }
+    ranked
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn load_cache(path: &Path) -> Option<RankingCache> {
+    let content = std::fs::read(path).ok()?;
+    serde_json::from_slice(&content)
+        .inspect_err(|e| warn!("ignoring corrupted registry ranking: {e}"))
+        .ok()
+}
+
+/// Time a registry takes to answer a `HEAD` request, `None` if it is unreachable or unhealthy.
+async fn probe(client: &Client, url: &Url, timeout: Duration) -> Option<Duration> {
+    let start = Instant::now();
+    match client.head(url.clone()).timeout(timeout).send().await {
+        Ok(response) if !response.status().is_server_error() => {
+            let latency = start.elapsed();
+            debug!("registry {url} answered in {latency:?}");
+            Some(latency)
+        }
+        Ok(response) => {
+            warn!("registry {url} is unhealthy: {}", response.status());
+            None
+        }
+        Err(e) => {
+            warn!("registry {url} is unreachable: {e}");
+            None
+        }
+    }
+}
+
+/// Sort by latency, failed registries go last in their configured order.
+fn order_by_latency(registries: &[Url], latencies: &[Option<Duration>]) -> Vec<Url> {
+    let mut ranked = registries.iter().zip(latencies).collect::<Vec<_>>();
+    ranked.sort_by_key(|(_, latency)| (latency.is_none(), latency.unwrap_or_default()));
+    ranked.into_iter().map(|(url, _)| url.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockRegistry, TempDir, mock_config};
+    use reqwest::ClientBuilder;
+
+    fn urls(hosts: &[&str]) -> Vec<Url> {
+        hosts
+            .iter()
+            .map(|host| Url::parse(&format!("https://{host}/")).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_order_by_latency() {
+        let registries = urls(&["a.test", "b.test", "c.test", "d.test"]);
+        let latencies = [
+            None,
+            Some(Duration::from_millis(300)),
+            None,
+            Some(Duration::from_millis(20)),
+        ];
+        assert_eq!(
+            order_by_latency(&registries, &latencies),
+            urls(&["d.test", "b.test", "a.test", "c.test"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rank_registries() {
+        let slow = MockRegistry::start().await;
+        slow.set_latency(Duration::from_millis(200));
+        let fast = MockRegistry::start().await;
+        let config = mock_config(&[&slow, &fast]);
+        let client = config
+            .network()
+            .apply(ClientBuilder::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        let cache_dir = TempDir::new("llc-rs-ranking");
+
+        let ranked = rank_registries(
+            &client,
+            config.npm_registries(),
+            config.registry_selection(),
+            Some(&cache_dir),
+        )
+        .await;
+        assert_eq!(ranked, [fast.url().clone(), slow.url().clone()]);
+
+        // the second ranking comes from the cache without probing
+        let ranked = rank_registries(
+            &client,
+            config.npm_registries(),
+            config.registry_selection(),
+            Some(&cache_dir),
+        )
+        .await;
+        assert_eq!(ranked, [fast.url().clone(), slow.url().clone()]);
+        assert_eq!(slow.requests().len(), 1);
+        assert_eq!(fast.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_ranking_cache_validity() {
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+        let cache = RankingCache {
+            probed_at: unix_seconds(now - Duration::from_secs(30)),
+            ranked: urls(&["b.test", "a.test"]),
+        };
+        assert!(cache.is_valid(&urls(&["a.test", "b.test"]), ttl, now));
+        assert!(!cache.is_valid(&urls(&["a.test", "c.test"]), ttl, now));
+        assert!(!cache.is_valid(&urls(&["a.test"]), ttl, now));
+        assert!(!cache.is_valid(
+            &urls(&["a.test", "b.test"]),
+            ttl,
+            now + Duration::from_secs(60)
+        ));
+        // a clock that went backwards doesn't keep a ranking alive forever
+        assert!(!cache.is_valid(
+            &urls(&["a.test", "b.test"]),
+            ttl,
+            now - Duration::from_secs(60)
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+//! Verification of registry signatures of published versions.
+//!
+//! The integrity in the metadata only proves that the tarball matches the metadata, which a
+//! mirror serves as well. Registry signatures tie the integrity to a key of the registry that
+//! published the version, and can't be forged by a mirror.
+//!
+//! Sigstore provenance attestations are not checked, that would take verifying their bundle
+//! against the Fulcio and Rekor trust roots rather than a subject the mirror serves.
+
+use crate::{RegistryKey, npm::VersionMetadata};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::signature::{ECDSA_P256_SHA256_ASN1, UnparsedPublicKey};
+
+/// DER prefix of a P-256 public key in SubjectPublicKeyInfo form, followed by the 65 byte point.
+pub(crate) const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("no registry signature")]
+    Missing,
+    #[error("signed by unknown key {0}")]
+    UnknownKey(String),
+    #[error("registry key {0} is not a base64 encoded P-256 public key")]
+    MalformedKey(String),
+    #[error("invalid signature by key {0}")]
+    Invalid(String),
+}
+
+/// Check every `dist.signatures` entry of `meta` against the registry `keys`.
+///
+/// The registry signs `<package>@<version>:<integrity>`, as verified by `npm audit signatures`.
+pub(crate) fn verify_signatures(
+    package: &str,
+    meta: &VersionMetadata,
+    keys: &[RegistryKey],
+) -> Result<(), SignatureError> {
+    let signatures = meta.dist.signatures();
+    if signatures.is_empty() {
+        return Err(SignatureError::Missing);
+    }
+    let message = format!("{package}@{}:{}", meta.version, meta.dist.integrity());
+    for signature in signatures {
+        let key = keys
+            .iter()
+            .find(|key| key.keyid() == signature.keyid)
+            .ok_or_else(|| SignatureError::UnknownKey(signature.keyid.clone()))?;
+        let public_key = p256_point(key.key())
+            .ok_or_else(|| SignatureError::MalformedKey(signature.keyid.clone()))?;
+        let sig = STANDARD
+            .decode(&signature.sig)
+            .map_err(|_| SignatureError::Invalid(signature.keyid.clone()))?;
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public_key)
+            .verify(message.as_bytes(), &sig)
+            .map_err(|_| SignatureError::Invalid(signature.keyid.clone()))?;
+    }
+    Ok(())
+}
+
+/// The uncompressed point of a base64 encoded P-256 SubjectPublicKeyInfo.
+fn p256_point(key: &str) -> Option<Vec<u8>> {
+    let der = STANDARD.decode(key).ok()?;
+    der.strip_prefix(P256_SPKI_PREFIX)
+        .filter(|point| point.len() == 65)
+        .map(<[u8]>::to_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::NPM_REGISTRY_KEYS,
+        test_support::{MOCK_KEYID, mock_public_key, mock_sign},
+    };
+    use serde_json::json;
+
+    fn version_metadata(version: &str, signatures: serde_json::Value) -> VersionMetadata {
+        serde_json::from_value(json!({
+            "version": version,
+            "dist": {
+                "integrity": "sha512-9/u6bgY2+JDlb7vzKD5STG+jIErimDgtYkdB0NxmODJuKCxBvl5CVNiCB3LFUYosWowMf37aGVlKfrU5RT4e1w==",
+                "tarball": "https://registry.npmjs.org/llc/-/llc-1.0.0.tgz",
+                "signatures": signatures,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        let meta = version_metadata("1.0.0", json!([]));
+        let keys = [RegistryKey::new(MOCK_KEYID, mock_public_key())];
+        assert!(matches!(
+            verify_signatures("llc", &meta, &keys),
+            Err(SignatureError::Missing)
+        ));
+
+        let sig = mock_sign(&format!("llc@1.0.0:{}", meta.dist.integrity()));
+        let signatures = json!([{ "keyid": MOCK_KEYID, "sig": sig }]);
+        let meta = version_metadata("1.0.0", signatures.clone());
+        verify_signatures("llc", &meta, &keys).unwrap();
+
+        // the signature covers name, version and integrity
+        assert!(matches!(
+            verify_signatures("llc-evil", &meta, &keys),
+            Err(SignatureError::Invalid(_))
+        ));
+        let forged = version_metadata("1.0.1", signatures);
+        assert!(matches!(
+            verify_signatures("llc", &forged, &keys),
+            Err(SignatureError::Invalid(_))
+        ));
+
+        let other_keys = [RegistryKey::new("SHA256:other", mock_public_key())];
+        assert!(matches!(
+            verify_signatures("llc", &meta, &other_keys),
+            Err(SignatureError::UnknownKey(keyid)) if keyid == MOCK_KEYID
+        ));
+        let malformed = [RegistryKey::new(MOCK_KEYID, "bm90IGEga2V5")];
+        assert!(matches!(
+            verify_signatures("llc", &meta, &malformed),
+            Err(SignatureError::MalformedKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_npm_registry_keys() {
+        for key in NPM_REGISTRY_KEYS.iter() {
+            assert!(p256_point(key.key()).is_some(), "{}", key.keyid());
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Content addressed store of downloaded tarballs, so that a version fetched before (e.g. after
+//! a reinstall or a rollback) doesn't have to be downloaded again.
+//!
+//! Like cacache, a tarball is stored under the hex digest of its integrity,
+//! `<root>/sha512/ab/cd/abcd…`. The modification time of an entry is its last use, the least
+//! recently used entries are removed first.
+
+use crate::TarballCacheConfig;
+use ssri::{Integrity, IntegrityChecker};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+/// Directory of the store inside the cache directory.
+pub(crate) const TARBALL_CACHE_DIR: &str = "tarballs";
+
+#[derive(Debug, Clone)]
+pub(crate) struct TarballCache {
+    root: PathBuf,
+    config: TarballCacheConfig,
+}
+
+impl TarballCache {
+    pub(crate) fn new(cache_dir: &Path, config: TarballCacheConfig) -> Self {
+        TarballCache {
+            root: cache_dir.join(TARBALL_CACHE_DIR),
+            config,
+        }
+    }
+
+    fn entry_path(&self, integrity: &Integrity) -> PathBuf {
+        let (algorithm, hex) = integrity.to_hex();
+        self.root
+            .join(algorithm.to_string())
+            .join(&hex[..2])
+            .join(&hex[2..4])
+            .join(&hex)
+    }
+
+    /// Copy the tarball with `integrity` to `dest`, returning its size.
+    ///
+    /// The entry is verified while copying, a corrupted entry is removed and `None` returned.
+    pub(crate) async fn get(&self, integrity: &Integrity, dest: &Path) -> Option<u64> {
+        let path = self.entry_path(integrity);
+        let copy = async {
+            let mut src = File::open(&path).await?;
+            let mut out = File::create(dest).await?;
+            let mut checker = IntegrityChecker::new(integrity.clone());
+            let mut buf = vec![0; 64 * 1024];
+            let mut size = 0;
+            loop {
+                let read = src.read(&mut buf).await?;
+                if read == 0 {
+                    break;
+                }
+                checker.input(&buf[..read]);
+                out.write_all(&buf[..read]).await?;
+                size += read as u64;
+            }
+            out.flush().await?;
+            Ok::<_, io::Error>((size, checker.result().is_ok()))
+        };
+        match copy.await {
+            Ok((size, true)) => {
+                touch(&path)
+                    .inspect_err(|e| warn!("failed to touch {}: {e}", path.display()))
+                    .ok();
+                Some(size)
+            }
+            Ok((_, false)) => {
+                warn!("removing corrupted cached tarball {}", path.display());
+                fs::remove_file(&path).ok();
+                fs::remove_file(dest).ok();
+                None
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("failed to read cached tarball {}: {e}", path.display());
+                fs::remove_file(dest).ok();
+                None
+            }
+        }
+    }
+
+    /// Store a copy of the verified tarball at `src`, then trim the store to its limits.
+    pub(crate) async fn insert(&self, integrity: &Integrity, src: &Path) {
+        if self.config.max_size() == 0 {
+            return;
+        }
+        let path = self.entry_path(integrity);
+        let tmp = self.root.join(format!("tmp-{:016x}", fastrand::u64(..)));
+        let insert = async {
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::copy(src, &tmp).await?;
+            // a concurrent reader sees either no entry or a complete one
+            tokio::fs::rename(&tmp, &path).await
+        };
+        if let Err(e) = insert.await {
+            warn!("failed to cache tarball {}: {e}", src.display());
+            tokio::fs::remove_file(&tmp).await.ok();
+            return;
+        }
+        self.collect_garbage()
+            .inspect_err(|e| warn!("failed to clean the tarball cache: {e}"))
+            .ok();
+    }
+
+    /// Remove entries unused for longer than the maximum age, then the least recently used ones
+    /// until the store fits its size limit. Returns the number of bytes freed.
+    pub(crate) fn collect_garbage(&self) -> io::Result<u64> {
+        let mut entries = vec![];
+        collect_entries(&self.root, &mut entries)?;
+        // most recently used first
+        entries.sort_by_key(|(_, _, used)| std::cmp::Reverse(*used));
+
+        let now = SystemTime::now();
+        let mut kept = 0;
+        let mut freed = 0;
+        for (path, size, used) in entries {
+            let expired = now
+                .duration_since(used)
+                .is_ok_and(|age| age > self.config.max_age());
+            if expired || kept + size > self.config.max_size() {
+                debug!("removing cached tarball {}", path.display());
+                fs::remove_file(&path)?;
+                freed += size;
+            } else {
+                kept += size;
+            }
+        }
+        Ok(freed)
+    }
+}
+
+/// Mark an entry as used now.
+fn touch(path: &Path) -> io::Result<()> {
+    fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// All entries below `dir` with their size and last use.
+fn collect_entries(dir: &Path, entries: &mut Vec<(PathBuf, u64, SystemTime)>) -> io::Result<()> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in read_dir {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_entries(&entry.path(), entries)?;
+        } else if !entry.file_name().to_string_lossy().starts_with("tmp-") {
+            entries.push((entry.path(), metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::time::Duration;
+    use tokio::test;
+
+    fn config(toml: &str) -> TarballCacheConfig {
+        ::toml::from_str(toml).unwrap()
+    }
+
+    async fn insert(cache: &TarballCache, dir: &Path, content: &[u8]) -> Integrity {
+        let src = dir.join("src.tgz");
+        tokio::fs::write(&src, content).await.unwrap();
+        let integrity = Integrity::from(content);
+        cache.insert(&integrity, &src).await;
+        integrity
+    }
+
+    #[test]
+    async fn test_get_and_insert() {
+        let dir = TempDir::new("llc-rs-tarball-cache");
+        let cache = TarballCache::new(&dir, TarballCacheConfig::default());
+        let dest = dir.join("dest.tgz");
+        assert!(cache.get(&Integrity::from(b"llc"), &dest).await.is_none());
+
+        let integrity = insert(&cache, &dir, b"llc").await;
+        assert_eq!(cache.get(&integrity, &dest).await, Some(3));
+        assert_eq!(std::fs::read(&dest).unwrap(), b"llc");
+
+        // a corrupted entry is dropped instead of handed out
+        std::fs::write(cache.entry_path(&integrity), b"evil").unwrap();
+        assert!(cache.get(&integrity, &dest).await.is_none());
+        assert!(!cache.entry_path(&integrity).exists());
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    async fn test_collect_garbage() {
+        let dir = TempDir::new("llc-rs-tarball-cache-gc");
+        // 1 MiB fits two of the entries below
+        let cache = TarballCache::new(&dir, config("max-size-mb = 1\nmax-age-days = 30"));
+        let entry = |byte| vec![byte; 400 * 1024];
+        let set_used = |integrity: &Integrity, days_ago: u64| {
+            fs::File::options()
+                .write(true)
+                .open(cache.entry_path(integrity))
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(days_ago * 24 * 60 * 60))
+                .unwrap()
+        };
+
+        let expired = insert(&cache, &dir, b"expired").await;
+        set_used(&expired, 60);
+        let old = insert(&cache, &dir, &entry(1)).await;
+        set_used(&old, 3);
+        let recent = insert(&cache, &dir, &entry(2)).await;
+        set_used(&recent, 2);
+        assert!(!cache.entry_path(&expired).exists());
+
+        // the least recently used entry makes room for the new one
+        let new = insert(&cache, &dir, &entry(3)).await;
+        assert!(!cache.entry_path(&old).exists());
+        assert!(cache.entry_path(&recent).exists());
+        assert!(cache.entry_path(&new).exists());
+    }
+}
@@ -0,0 +1,616 @@
+//! In-process stand-in for an npm registry, so that tests don't depend on the live registries.
+//!
+//! The server speaks just enough HTTP/1.1 for [`reqwest`]: one request per connection, `GET` and
+//! `HEAD`, `Range` requests on tarballs and `If-None-Match` on package documents.
+//!
+//! Versions are signed with [`MOCK_KEYID`], a key shared by all mock registries and served from
+//! their `/-/npm/v1/keys`.
+
+use crate::{LLCConfig, signature::P256_SPKI_PREFIX, utils::ResultExt};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::{Compression, write::GzEncoder};
+use ring::{
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use semver::Version;
+use serde_json::{Value, json};
+use ssri::{Algorithm, Integrity, IntegrityOpts};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use url::Url;
+
+/// Key id of the registry key mock registries sign versions with.
+pub const MOCK_KEYID: &str = "SHA256:mock-registry-key";
+
+static SIGNING_KEY: LazyLock<EcdsaKeyPair> = LazyLock::new(|| {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .expect("failed to generate mock registry key");
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+        .expect("failed to load mock registry key")
+});
+
+/// The public key of [`MOCK_KEYID`], base64 encoded in SubjectPublicKeyInfo form.
+pub fn mock_public_key() -> String {
+    STANDARD.encode([P256_SPKI_PREFIX, SIGNING_KEY.public_key().as_ref()].concat())
+}
+
+/// Base64 encoded signature of `message` by [`MOCK_KEYID`].
+pub fn mock_sign(message: &str) -> String {
+    let signature = SIGNING_KEY
+        .sign(&SystemRandom::new(), message.as_bytes())
+        .infallible();
+    STANDARD.encode(signature)
+}
+
+/// A package version to publish on a [`MockRegistry`].
+#[derive(Debug, Clone)]
+pub struct Package {
+    name: String,
+    version: Version,
+    tarball: Vec<u8>,
+    github_tag: Option<String>,
+    deprecated: Option<String>,
+    signed: bool,
+    signature: Option<String>,
+}
+
+impl Package {
+    pub fn new(name: &str, version: &str, tarball: impl Into<Vec<u8>>) -> Self {
+        Package {
+            name: name.to_string(),
+            version: Version::parse(version).expect("invalid version"),
+            tarball: tarball.into(),
+            github_tag: None,
+            deprecated: None,
+            signed: true,
+            signature: None,
+        }
+    }
+
+    /// Publish the version without a registry signature.
+    pub fn unsigned(mut self) -> Self {
+        self.signed = false;
+        self
+    }
+
+    /// Set the `githubTag` field of the version manifest.
+    pub fn github_tag(mut self, tag: &str) -> Self {
+        self.github_tag = Some(tag.to_string());
+        self
+    }
+
+    /// Mark the version as deprecated with `message`.
+    pub fn deprecated(mut self, message: &str) -> Self {
+        self.deprecated = Some(message.to_string());
+        self
+    }
+
+    /// Path of the tarball relative to the registry, following the `/-/` convention.
+    fn tarball_path(&self) -> String {
+        let base_name = self.name.rsplit('/').next().unwrap_or(&self.name);
+        format!("{}/-/{base_name}-{}.tgz", self.name, self.version)
+    }
+
+    fn integrity(&self) -> Integrity {
+        IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(&self.tarball)
+            .result()
+    }
+}
+
+/// A request received by a [`MockRegistry`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RecordedRequest {
+    /// Value of a request header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    packages: BTreeMap<String, Vec<Package>>,
+    dist_tags: BTreeMap<String, BTreeMap<String, Version>>,
+    tarballs: HashMap<String, Vec<u8>>,
+    latency: Duration,
+    failures: Vec<u16>,
+    truncate_next: Option<usize>,
+    corrupt_tarballs: bool,
+    tarball_failure: Option<u16>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A local npm registry listening on a loopback port, shut down when dropped.
+#[derive(Debug)]
+pub struct MockRegistry {
+    url: Url,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRegistry {
+    /// Start a registry on a random loopback port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind mock registry");
+        let addr = listener.local_addr().infallible();
+        let url = Url::parse(&format!("http://{addr}/")).infallible();
+        let state = Arc::new(Mutex::new(State::default()));
+        let task = tokio::spawn({
+            let url = url.clone();
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, url.clone(), state.clone()));
+                }
+            }
+        });
+        MockRegistry { url, state, task }
+    }
+
+    /// Base URL of the registry.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Full URL of the tarball of `package` on this registry.
+    pub fn tarball_url(&self, package: &Package) -> Url {
+        self.url.join(&package.tarball_path()).infallible()
+    }
+
+    /// Make `package` available, signed by [`MOCK_KEYID`] unless it is [`Package::unsigned`].
+    /// Unless tagged otherwise, the highest published release is tagged `latest`.
+    pub fn publish(&self, mut package: Package) {
+        if package.signed {
+            // signed once, ECDSA signatures differ every time
+            package.signature = Some(mock_sign(&format!(
+                "{}@{}:{}",
+                package.name,
+                package.version,
+                package.integrity()
+            )));
+        }
+        let mut state = self.state();
+        state
+            .tarballs
+            .insert(package.tarball_path(), package.tarball.clone());
+        state
+            .packages
+            .entry(package.name.clone())
+            .or_default()
+            .push(package);
+    }
+
+    /// Point the dist-tag `tag` of package `name` at `version`.
+    pub fn tag(&self, name: &str, tag: &str, version: &str) {
+        self.state()
+            .dist_tags
+            .entry(name.to_string())
+            .or_default()
+            .insert(
+                tag.to_string(),
+                Version::parse(version).expect("invalid version"),
+            );
+    }
+
+    /// Delay every response by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+
+    /// Answer the next `count` requests with `status` and an empty body.
+    pub fn fail_next(&self, count: usize, status: u16) {
+        self.state()
+            .failures
+            .extend(std::iter::repeat_n(status, count));
+    }
+
+    /// Close the connection after `bytes` of the next tarball body.
+    pub fn truncate_next(&self, bytes: usize) {
+        self.state().truncate_next = Some(bytes);
+    }
+
+    /// Serve tarballs whose content doesn't match their published integrity.
+    pub fn corrupt_tarballs(&self, corrupt: bool) {
+        self.state().corrupt_tarballs = corrupt;
+    }
+
+    /// Answer every tarball request with `status`, while documents are still served.
+    pub fn fail_tarballs(&self, status: Option<u16>) {
+        self.state().tarball_failure = status;
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state().requests.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("mock registry state poisoned")
+    }
+}
+
+impl Drop for MockRegistry {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A config using `registries` in order, allowing their plain HTTP URLs and retrying without
+/// delay.
+pub fn mock_config(registries: &[&MockRegistry]) -> LLCConfig {
+    LLCConfig::for_local_registries(
+        registries
+            .iter()
+            .map(|registry| registry.url.clone())
+            .collect(),
+    )
+}
+
+/// Build a gzipped package tarball, `files` are placed under `package/` as npm does.
+pub fn npm_tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, format!("package/{path}"), *content)
+            .infallible();
+    }
+    builder.into_inner().infallible().finish().infallible()
+}
+
+/// A fresh directory under the system temp dir, removed when dropped.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "{prefix}-{}-{:016x}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&path).expect("failed to create temp dir");
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+    /// Bytes of the body actually sent before closing the connection.
+    truncate: Option<usize>,
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: vec![],
+            truncate: None,
+        }
+    }
+
+    fn json(value: Value) -> Self {
+        let mut response = Response::new(200);
+        response
+            .headers
+            .push(("Content-Type", "application/json".to_string()));
+        response.body = serde_json::to_vec(&value).infallible();
+        response
+    }
+
+    /// A registry document with an `ETag`, `304 Not Modified` if the client has it already.
+    fn document(value: Value, content_type: &str, request: &RecordedRequest) -> Self {
+        let body = serde_json::to_vec(&value).infallible();
+        let etag = etag(&body);
+        let mut response = if request.header("If-None-Match") == Some(etag.as_str()) {
+            Response::new(304)
+        } else {
+            let mut response = Response::new(200);
+            response
+                .headers
+                .push(("Content-Type", content_type.to_string()));
+            response.body = body;
+            response
+        };
+        response.headers.push(("ETag", etag));
+        response
+    }
+}
+
+/// Strong `ETag` of `content`.
+fn etag(content: &[u8]) -> String {
+    format!(
+        "\"{}\"",
+        IntegrityOpts::new()
+            .algorithm(Algorithm::Sha1)
+            .chain(content)
+            .result()
+            .to_hex()
+            .1
+    )
+}
+
+async fn serve(mut stream: TcpStream, base_url: Url, state: Arc<Mutex<State>>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let (latency, response) = {
+        let mut state = state.lock().expect("mock registry state poisoned");
+        state.requests.push(request.clone());
+        (state.latency, respond(&mut state, &base_url, &request))
+    };
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    let mut body = response.body.as_slice();
+    if request.method == "HEAD" {
+        body = &[];
+    } else if let Some(truncate) = response.truncate {
+        body = &body[..truncate.min(body.len())];
+    }
+    if stream.write_all(head.as_bytes()).await.is_ok() {
+        stream.write_all(body).await.ok();
+    }
+    stream.shutdown().await.ok();
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<RecordedRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 || buf.len() > 64 * 1024 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+    })
+}
+
+fn respond(state: &mut State, base_url: &Url, request: &RecordedRequest) -> Response {
+    if !state.failures.is_empty() {
+        return Response::new(state.failures.remove(0));
+    }
+    let path = request.path.trim_start_matches('/').replace("%2f", "/");
+    let path = path.replace("%2F", "/");
+    if path.is_empty() {
+        return Response::json(json!({}));
+    }
+    if path == "-/npm/v1/keys" {
+        return Response::json(json!({
+            "keys": [{
+                "expires": null,
+                "keyid": MOCK_KEYID,
+                "keytype": "ecdsa-sha2-nistp256",
+                "scheme": "ecdsa-sha2-nistp256",
+                "key": mock_public_key(),
+            }],
+        }));
+    }
+    if let Some(tarball) = state.tarballs.get(&path) {
+        if let Some(status) = state.tarball_failure {
+            return Response::new(status);
+        }
+        let mut tarball = tarball.clone();
+        if state.corrupt_tarballs
+            && let Some(byte) = tarball.last_mut()
+        {
+            *byte ^= 0xff;
+        }
+        let mut response = tarball_response(tarball, request);
+        if response.status / 100 == 2 {
+            response.truncate = state.truncate_next.take();
+        }
+        return response;
+    }
+    if let Some(versions) = state.packages.get(&path) {
+        let tags = state.dist_tags.get(&path).cloned().unwrap_or_default();
+        let abbreviated = request
+            .header("Accept")
+            .is_some_and(|accept| accept.contains(ABBREVIATED));
+        let packument = packument(&path, versions, tags, base_url, abbreviated);
+        let content_type = if abbreviated {
+            ABBREVIATED
+        } else {
+            "application/json"
+        };
+        return Response::document(packument, content_type, request);
+    }
+    if let Some((name, version)) = path.rsplit_once('/')
+        && let Some(package) = state
+            .packages
+            .get(name)
+            .and_then(|versions| versions.iter().find(|p| p.version.to_string() == version))
+    {
+        return Response::document(manifest(package, base_url), "application/json", request);
+    }
+    Response::new(404)
+}
+
+fn tarball_response(tarball: Vec<u8>, request: &RecordedRequest) -> Response {
+    let etag = etag(&tarball);
+    let len = tarball.len();
+    let range_start = request
+        .header("Range")
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.strip_suffix('-'))
+        .and_then(|start| start.parse::<usize>().ok())
+        .filter(|_| request.header("If-Range").is_none_or(|tag| tag == etag));
+
+    let mut response = match range_start {
+        Some(start) if start >= len => {
+            let mut response = Response::new(416);
+            response
+                .headers
+                .push(("Content-Range", format!("bytes */{len}")));
+            return response;
+        }
+        Some(start) => {
+            let mut response = Response::new(206);
+            response
+                .headers
+                .push(("Content-Range", format!("bytes {start}-{}/{len}", len - 1)));
+            response.body = tarball[start..].to_vec();
+            response
+        }
+        None => {
+            let mut response = Response::new(200);
+            response.body = tarball;
+            response
+        }
+    };
+    response
+        .headers
+        .push(("Content-Type", "application/octet-stream".to_string()));
+    response
+        .headers
+        .push(("Accept-Ranges", "bytes".to_string()));
+    response.headers.push(("ETag", etag));
+    response
+}
+
+/// Media type of the abbreviated package document.
+const ABBREVIATED: &str = "application/vnd.npm.install-v1+json";
+
+/// The full version manifest of `package`.
+fn manifest(package: &Package, base_url: &Url) -> Value {
+    let mut manifest = json!({
+        "name": package.name,
+        "version": package.version.to_string(),
+        "dist": {
+            "integrity": package.integrity().to_string(),
+            "tarball": base_url.join(&package.tarball_path()).infallible(),
+            "unpackedSize": package.tarball.len(),
+        },
+    });
+    if let Some(signature) = &package.signature {
+        manifest["dist"]["signatures"] = json!([{ "keyid": MOCK_KEYID, "sig": signature }]);
+    }
+    if let Some(tag) = &package.github_tag {
+        manifest["githubTag"] = json!(tag);
+    }
+    if let Some(message) = &package.deprecated {
+        manifest["deprecated"] = json!(message);
+    }
+    manifest
+}
+
+/// The package document, `abbreviated` leaves out publish times and fields not needed to
+/// install, like `githubTag`.
+fn packument(
+    name: &str,
+    versions: &[Package],
+    mut dist_tags: BTreeMap<String, Version>,
+    base_url: &Url,
+    abbreviated: bool,
+) -> Value {
+    if let Some(latest) = versions
+        .iter()
+        .map(|package| &package.version)
+        .max_by_key(|version| (version.pre.is_empty(), *version))
+    {
+        dist_tags
+            .entry("latest".to_string())
+            .or_insert_with(|| latest.clone());
+    }
+    let versions = versions
+        .iter()
+        .map(|package| {
+            let mut manifest = manifest(package, base_url);
+            if abbreviated && let Some(manifest) = manifest.as_object_mut() {
+                manifest.remove("githubTag");
+            }
+            (package.version.to_string(), manifest)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    if abbreviated {
+        return json!({
+            "name": name,
+            "dist-tags": dist_tags,
+            "versions": versions,
+        });
+    }
+    // made up publish times, a day apart
+    let time = versions
+        .keys()
+        .enumerate()
+        .map(|(day, version)| {
+            (
+                version.clone(),
+                json!(format!("1970-01-{:02}T00:00:00.000Z", day % 28 + 1)),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "name": name,
+        "dist-tags": dist_tags,
+        "versions": versions,
+        "time": time,
+    })
+}
@@ -1,72 +1,380 @@
-use bytes::Bytes;
-use futures_util::{TryFutureExt, TryStreamExt};
-use reqwest::Response;
-use std::{fmt::Debug, path::Path};
-use tokio::io::AsyncWriteExt;
+use crate::RetryPolicy;
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, TryFutureExt, TryStreamExt};
+use reqwest::{
+    RequestBuilder, Response, StatusCode,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    path::{Path, PathBuf},
+    pin::pin,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use url::Url;
 
+pub use tokio_util::sync::CancellationToken;
+
+/// Most bytes reserved up front for an in-memory download, the `Content-Length` is only a hint.
+const MAX_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ReqwestExtError {
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
+    #[error("download cancelled")]
+    Cancelled,
+    #[error("server responded with an unexpected content range")]
+    InvalidRange,
+    #[error("download rejected: {0}")]
+    Rejected(#[source] Box<dyn Error + Send + Sync>),
+    #[error(transparent)]
+    Mirrors(#[from] MirrorErrors),
+}
+
+/// A URL to request, with the credentials and headers its server expects.
+#[derive(Clone)]
+pub struct Target {
+    url: Url,
+    bearer: Option<String>,
+    headers: HeaderMap,
+}
+
+impl Target {
+    pub fn new(url: Url) -> Self {
+        Target {
+            url,
+            bearer: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Send `token` as `Authorization: Bearer` with requests to this URL.
+    pub fn bearer(mut self, token: impl Into<String>) -> Self {
+        self.bearer = Some(token.into());
+        self
+    }
+
+    /// Send `name: value` with requests to this URL, replacing the client default.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn get(&self, client: &reqwest::Client) -> RequestBuilder {
+        let req = client.get(self.url.clone()).headers(self.headers.clone());
+        match &self.bearer {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+}
+
+impl From<Url> for Target {
+    fn from(url: Url) -> Self {
+        Target::new(url)
+    }
+}
+
+impl Debug for Target {
+    // keep tokens out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Target")
+            .field("url", &self.url.as_str())
+            .field("bearer", &self.bearer.as_ref().map(|_| "<redacted>"))
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+/// Coarse reason a mirror failed, for a readable summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    Status(StatusCode),
+    Timeout,
+    Dns,
+    Tls,
+    Connect,
+    Other,
+}
+
+impl FailureKind {
+    /// Classify an error returned while talking to a mirror.
+    pub fn of(err: &ReqwestExtError) -> Self {
+        let ReqwestExtError::Reqwest(e) = err else {
+            return FailureKind::Other;
+        };
+        if let Some(status) = e.status() {
+            return FailureKind::Status(status);
+        }
+        if e.is_timeout() {
+            return FailureKind::Timeout;
+        }
+        // reqwest doesn't expose DNS and TLS failures, look for them in the source chain
+        let mut source = e.source();
+        while let Some(cause) = source {
+            let message = cause.to_string().to_ascii_lowercase();
+            if message.contains("dns error") || message.contains("failed to lookup address") {
+                return FailureKind::Dns;
+            }
+            if message.contains("certificate") || message.contains("tls") {
+                return FailureKind::Tls;
+            }
+            source = cause.source();
+        }
+        if e.is_connect() {
+            FailureKind::Connect
+        } else {
+            FailureKind::Other
+        }
+    }
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::Status(status) => write!(f, "HTTP {status}"),
+            FailureKind::Timeout => write!(f, "timed out"),
+            FailureKind::Dns => write!(f, "DNS lookup failed"),
+            FailureKind::Tls => write!(f, "TLS error"),
+            FailureKind::Connect => write!(f, "connection failed"),
+            FailureKind::Other => write!(f, "error"),
+        }
+    }
+}
+
+/// Failure of a single mirror after all its attempts.
+#[derive(Debug)]
+pub struct MirrorError {
+    pub url: Url,
+    pub attempts: u32,
+    pub kind: FailureKind,
+    pub error: ReqwestExtError,
+}
+
+/// Failures of every mirror that was tried, in order.
+#[derive(Debug, Default)]
+pub struct MirrorErrors(Vec<MirrorError>);
+
+impl MirrorErrors {
+    pub fn push(&mut self, url: Url, attempts: u32, error: ReqwestExtError) {
+        self.0.push(MirrorError {
+            url,
+            attempts,
+            kind: FailureKind::of(&error),
+            error,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MirrorError> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for MirrorErrors {
+    type Item = MirrorError;
+    type IntoIter = std::vec::IntoIter<MirrorError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Display for MirrorErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.len() {
+            0 => return write!(f, "no mirror to try"),
+            1 => write!(f, "mirror failed:")?,
+            n => write!(f, "all {n} mirrors failed:")?,
+        }
+        for MirrorError {
+            url,
+            attempts,
+            kind,
+            error,
+        } in &self.0
+        {
+            write!(f, "\n  {url}: {kind}")?;
+            if *kind == FailureKind::Other {
+                write!(f, " ({error})")?;
+            }
+            if *attempts > 1 {
+                write!(f, ", after {attempts} attempts")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for MirrorErrors {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.last().map(|e| &e.error as _)
+    }
+}
+
+/// Observer of a streaming download.
+pub trait DownloadObserver: Send {
+    /// Called once the response headers arrive and after each chunk, with the bytes received so
+    /// far and the expected total from `Content-Length`, if known.
+    fn on_progress(&mut self, received: u64, total: Option<u64>);
+
+    /// Called with the content in order, including any bytes resumed from a partial file.
+    fn on_chunk(&mut self, _chunk: &[u8]) {}
+
+    /// Called before the content is fed again from the start, e.g. when a download resumes or
+    /// the server ignored a range request.
+    fn on_restart(&mut self) {}
+
+    /// Called once the whole content has been received, before it is moved into place.
+    ///
+    /// Returning an error discards the content and moves on to the next URL.
+    fn verify(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(())
+    }
+}
+
+impl<F> DownloadObserver for F
+where
+    F: FnMut(u64, Option<u64>) + Send,
+{
+    fn on_progress(&mut self, received: u64, total: Option<u64>) {
+        self(received, total)
+    }
 }
 
 pub trait ClientExt {
-    fn try_get<I>(&self, urls: I) -> impl Future<Output = Result<Response, ReqwestExtError>> + Send
+    /// Get the first successful response from the given URLs, retrying each URL according to
+    /// `policy` before moving on to the next one.
+    fn try_get<I>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+    ) -> impl Future<Output = Result<Response, ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send;
+        I: Iterator<Item: Into<Target> + Send> + Send;
 
     /// Download the content from one of the given URLs.
-    fn download<I>(&self, urls: I) -> impl Future<Output = Result<Bytes, ReqwestExtError>> + Send
+    fn download<I>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+    ) -> impl Future<Output = Result<Bytes, ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send;
+        I: Iterator<Item: Into<Target> + Send> + Send;
 
     /// Download the content from one of the given URLs to the given destination path.
+    ///
+    /// The content is first written to `<dest>.part`. If the connection drops midway, the
+    /// download resumes with a `Range` request (validated by `ETag`/`Last-Modified`) from the
+    /// same mirror, then from the next one; the partial file is also kept for a later call.
     fn download_to<I, P>(
         &self,
         urls: I,
         dest: P,
+        policy: &RetryPolicy,
     ) -> impl Future<Output = Result<(), ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
         P: AsRef<Path>;
 
+    /// Like [`ClientExt::download`], reporting progress to `observer` and stopping with
+    /// [`ReqwestExtError::Cancelled`] once `cancel` is triggered.
+    fn download_with_progress<I, O>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+        observer: O,
+        cancel: &CancellationToken,
+    ) -> impl Future<Output = Result<Bytes, ReqwestExtError>> + Send
+    where
+        I: Iterator<Item: Into<Target> + Send> + Send,
+        O: DownloadObserver;
+
+    /// Like [`ClientExt::download_to`], reporting progress to `observer` and stopping with
+    /// [`ReqwestExtError::Cancelled`] once `cancel` is triggered.
+    ///
+    /// Returns the number of bytes written.
+    fn download_to_with_progress<I, P, O>(
+        &self,
+        urls: I,
+        dest: P,
+        policy: &RetryPolicy,
+        observer: O,
+        cancel: &CancellationToken,
+    ) -> impl Future<Output = Result<u64, ReqwestExtError>> + Send
+    where
+        I: Iterator<Item: Into<Target> + Send> + Send,
+        P: AsRef<Path>,
+        O: DownloadObserver;
+
     /// Get and deserialize JSON content from one of the given URLs.
-    fn get_json<I, T>(&self, urls: I) -> impl Future<Output = Result<T, ReqwestExtError>> + Send
+    fn get_json<I, T>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+    ) -> impl Future<Output = Result<T, ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
         T: serde::de::DeserializeOwned + Send + 'static;
 }
 
 impl ClientExt for reqwest::Client {
-    async fn try_get<I>(&self, urls: I) -> Result<Response, ReqwestExtError>
+    async fn try_get<I>(&self, urls: I, policy: &RetryPolicy) -> Result<Response, ReqwestExtError>
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
     {
-        let mut last_err = None;
-        for url in urls {
-            match self
-                .get(url)
-                .send()
-                .await
-                .and_then(|res| res.error_for_status())
-            {
-                Ok(res) => return Ok(res),
-                Err(e) => {
-                    last_err = Some(e);
+        let mut errors = MirrorErrors::default();
+        for target in urls.map(Into::<Target>::into) {
+            let url = &target.url;
+            for attempt in 1..=policy.attempts() {
+                match target
+                    .get(self)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                {
+                    Ok(res) => return Ok(res),
+                    Err(e) => {
+                        let e = ReqwestExtError::from(e);
+                        let retry = attempt < policy.attempts() && policy.is_retryable(&e);
+                        warn!("error requesting {url} (attempt {attempt}): {e}");
+                        if !retry {
+                            errors.push(url.clone(), attempt, e);
+                            break;
+                        }
+                        tokio::time::sleep(policy.backoff(attempt)).await;
+                    }
                 }
             }
         }
-        Err(last_err.infallible().into())
+        Err(errors.into())
     }
 
-    fn download<I>(&self, urls: I) -> impl Future<Output = Result<Bytes, ReqwestExtError>> + Send
+    fn download<I>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+    ) -> impl Future<Output = Result<Bytes, ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
     {
-        self.try_get(urls)
+        self.try_get(urls, policy)
             .and_then(|res| res.bytes().map_err(|e| e.into()))
     }
 
@@ -74,32 +382,329 @@ impl ClientExt for reqwest::Client {
         &self,
         urls: I,
         dest: P,
+        policy: &RetryPolicy,
     ) -> impl Future<Output = Result<(), ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
         P: AsRef<Path>,
     {
         let dest = dest.as_ref().to_path_buf();
-        self.try_get(urls).and_then(move |res| async move {
-            let mut file = tokio::fs::File::create(&dest).await?;
-            let mut stream = res.bytes_stream();
-            while let Some(chunk) = stream.try_next().await? {
-                file.write_all(&chunk).await?;
+        async move {
+            let cancel = CancellationToken::new();
+            self.download_to_with_progress(urls, &dest, policy, |_, _| {}, &cancel)
+                .await
+                .map(|_| ())
+        }
+    }
+
+    async fn download_with_progress<I, O>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+        mut observer: O,
+        cancel: &CancellationToken,
+    ) -> Result<Bytes, ReqwestExtError>
+    where
+        I: Iterator<Item: Into<Target> + Send> + Send,
+        O: DownloadObserver,
+    {
+        let res = cancel
+            .run_until_cancelled(self.try_get(urls, policy))
+            .await
+            .ok_or(ReqwestExtError::Cancelled)??;
+        let total = res.content_length();
+        let mut buffer =
+            BytesMut::with_capacity(total.unwrap_or_default().min(MAX_PREALLOCATION) as usize);
+        observer.on_progress(0, total);
+
+        let mut stream = pin!(res.bytes_stream());
+        while let Some(chunk) = next_chunk(&mut stream, cancel).await? {
+            observer.on_chunk(&chunk);
+            buffer.extend_from_slice(&chunk);
+            observer.on_progress(buffer.len() as u64, total);
+        }
+        observer.verify().map_err(ReqwestExtError::Rejected)?;
+        Ok(buffer.freeze())
+    }
+
+    fn download_to_with_progress<I, P, O>(
+        &self,
+        urls: I,
+        dest: P,
+        policy: &RetryPolicy,
+        mut observer: O,
+        cancel: &CancellationToken,
+    ) -> impl Future<Output = Result<u64, ReqwestExtError>> + Send
+    where
+        I: Iterator<Item: Into<Target> + Send> + Send,
+        P: AsRef<Path>,
+        O: DownloadObserver,
+    {
+        let dest = dest.as_ref().to_path_buf();
+        async move {
+            let part = path_with_suffix(&dest, ".part");
+            let meta_path = path_with_suffix(&dest, ".part.meta");
+            let mut meta = PartialMeta::load(&part, &meta_path).await;
+
+            let mut errors = MirrorErrors::default();
+            for target in urls.map(Into::<Target>::into) {
+                let url = &target.url;
+                let mut attempt = 1;
+                loop {
+                    let before = file_len(&part).await;
+                    match download_part(
+                        self,
+                        &target,
+                        &part,
+                        &meta_path,
+                        &mut meta,
+                        &mut observer,
+                        cancel,
+                    )
+                    .await
+                    {
+                        Ok(written) => {
+                            tokio::fs::remove_file(&meta_path).await.ok();
+                            if let Err(e) = observer.verify() {
+                                warn!("download from {url} rejected: {e}");
+                                tokio::fs::remove_file(&part).await.ok();
+                                meta = None;
+                                errors.push(url.clone(), attempt, ReqwestExtError::Rejected(e));
+                                break;
+                            }
+                            tokio::fs::rename(&part, &dest).await?;
+                            return Ok(written);
+                        }
+                        Err(ReqwestExtError::Cancelled) => return Err(ReqwestExtError::Cancelled),
+                        Err(e) => {
+                            warn!("error downloading from {url} (attempt {attempt}): {e}");
+                            // an attempt that got further doesn't count against the policy
+                            let retry = if file_len(&part).await > before {
+                                attempt = 1;
+                                true
+                            } else if attempt < policy.attempts() && policy.is_retryable(&e) {
+                                tokio::time::sleep(policy.backoff(attempt)).await;
+                                attempt += 1;
+                                true
+                            } else {
+                                false
+                            };
+                            if !retry {
+                                errors.push(url.clone(), attempt, e);
+                                break;
+                            }
+                            info!("resuming download from {url}");
+                        }
+                    }
+                }
             }
-            Ok(())
-        })
+            Err(errors.into())
+        }
     }
 
-    fn get_json<I, T>(&self, urls: I) -> impl Future<Output = Result<T, ReqwestExtError>> + Send
+    fn get_json<I, T>(
+        &self,
+        urls: I,
+        policy: &RetryPolicy,
+    ) -> impl Future<Output = Result<T, ReqwestExtError>> + Send
     where
-        I: Iterator<Item = Url> + Send,
+        I: Iterator<Item: Into<Target> + Send> + Send,
         T: serde::de::DeserializeOwned + Send + 'static,
     {
-        self.try_get(urls)
+        self.try_get(urls, policy)
             .and_then(|res| res.json::<T>().map_err(|e| e.into()))
     }
 }
 
+/// Validators of a partially downloaded file, stored next to it so that a later attempt (even
+/// from another mirror or another launch) can resume with a `Range` request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PartialMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl PartialMeta {
+    /// Load the validators of an existing partial file, discarding partial files we can't trust.
+    async fn load(part: &Path, meta_path: &Path) -> Option<Self> {
+        let meta = tokio::fs::read(meta_path)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok());
+        if meta.is_none() {
+            tokio::fs::remove_file(part).await.ok();
+        }
+        meta
+    }
+
+    fn from_response(res: &Response) -> Self {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        PartialMeta {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        }
+    }
+
+    /// Value for the `If-Range` header, weak ETags are not allowed there.
+    fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    async fn save(&self, meta_path: &Path) -> std::io::Result<()> {
+        tokio::fs::write(meta_path, serde_json::to_vec(self).infallible()).await
+    }
+}
+
+/// Download `target` into `part`, resuming from its current length when `meta` allows it.
+///
+/// Returns the length of the complete file.
+async fn download_part<O: DownloadObserver>(
+    client: &reqwest::Client,
+    target: &Target,
+    part: &Path,
+    meta_path: &Path,
+    meta: &mut Option<PartialMeta>,
+    observer: &mut O,
+    cancel: &CancellationToken,
+) -> Result<u64, ReqwestExtError> {
+    let url = &target.url;
+    let send = |offset: u64, if_range: Option<String>| {
+        let mut req = target.get(client);
+        if let Some(if_range) = if_range
+            && offset > 0
+        {
+            req = req
+                .header(header::RANGE, format!("bytes={offset}-"))
+                .header(header::IF_RANGE, if_range);
+        }
+        cancel.run_until_cancelled(req.send())
+    };
+
+    let mut offset = file_len(part).await;
+    let if_range = meta
+        .as_ref()
+        .and_then(PartialMeta::if_range)
+        .map(str::to_string);
+    let mut res = send(offset, if_range)
+        .await
+        .ok_or(ReqwestExtError::Cancelled)??;
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        warn!("server rejected resume from byte {offset}, restarting download");
+        offset = 0;
+        res = send(0, None).await.ok_or(ReqwestExtError::Cancelled)??;
+    }
+    let res = res.error_for_status()?;
+
+    let (mut file, total) = if res.status() == StatusCode::PARTIAL_CONTENT {
+        let Some((start, total)) = res
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range)
+            .filter(|(start, _)| *start == offset && offset > 0)
+        else {
+            // not the range we asked for, start over on the next attempt
+            *meta = None;
+            tokio::fs::remove_file(part).await.ok();
+            return Err(ReqwestExtError::InvalidRange);
+        };
+        debug!("resuming download of {url} from byte {start}");
+        observer.on_restart();
+        replay_partial(part, observer).await?;
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(part)
+            .await?;
+        let total = total.or(res.content_length().map(|len| start + len));
+        (file, total)
+    } else {
+        if offset > 0 {
+            info!("server ignored range request for {url}, restarting download");
+        }
+        offset = 0;
+        observer.on_restart();
+        let new_meta = PartialMeta::from_response(&res);
+        new_meta.save(meta_path).await?;
+        *meta = Some(new_meta);
+        (tokio::fs::File::create(part).await?, res.content_length())
+    };
+
+    let mut received = offset;
+    observer.on_progress(received, total);
+    let mut stream = pin!(res.bytes_stream());
+    let streamed = async {
+        while let Some(chunk) = next_chunk(&mut stream, cancel).await? {
+            observer.on_chunk(&chunk);
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            observer.on_progress(received, total);
+        }
+        Ok::<_, ReqwestExtError>(())
+    }
+    .await;
+    // make sure whatever arrived is on disk before the next attempt resumes from it
+    file.flush().await?;
+    streamed?;
+    Ok(received)
+}
+
+/// Feed the content of an existing partial file to `observer`.
+async fn replay_partial<O: DownloadObserver>(part: &Path, observer: &mut O) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(part).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        observer.on_chunk(&buffer[..read]);
+    }
+}
+
+/// Parse the start offset and complete length from a `Content-Range: bytes a-b/len` header.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.len())
+        .unwrap_or_default()
+}
+
+/// Pull the next chunk from a response body, unless `cancel` fires first.
+async fn next_chunk<S>(
+    stream: &mut std::pin::Pin<&mut S>,
+    cancel: &CancellationToken,
+) -> Result<Option<Bytes>, ReqwestExtError>
+where
+    S: Stream<Item = reqwest::Result<Bytes>>,
+{
+    Ok(cancel
+        .run_until_cancelled(stream.try_next())
+        .await
+        .ok_or(ReqwestExtError::Cancelled)??)
+}
+
 pub trait OptionExt<T> {
     fn infallible(self) -> T;
 }
@@ -131,3 +736,74 @@ impl<T, E: Debug> ResultExt<T, E> for Result<T, E> {
         self.expect("infallible")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DEFAULT_CLIENT, LLCConfig, test_support::MockRegistry};
+    use tokio::test;
+
+    #[test]
+    async fn test_download_cancelled() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let url = Url::parse("https://registry.npmjs.org/@lightsing/llc-zh-cn").unwrap();
+        let res = DEFAULT_CLIENT
+            .download_with_progress(
+                [url].into_iter(),
+                &RetryPolicy::default(),
+                |_, _| {},
+                &cancel,
+            )
+            .await;
+        assert!(matches!(res, Err(ReqwestExtError::Cancelled)));
+    }
+
+    #[test]
+    async fn test_try_get_collects_mirror_errors() {
+        let failing = MockRegistry::start().await;
+        failing.fail_next(1, 503);
+        // the port is closed once the registry is dropped
+        let closed = MockRegistry::start().await.url().clone();
+        let urls = [
+            failing.url().join("pkg").unwrap(),
+            closed.join("pkg").unwrap(),
+        ];
+        let client = LLCConfig::for_local_registries(vec![])
+            .network()
+            .apply(reqwest::ClientBuilder::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        let Err(ReqwestExtError::Mirrors(errors)) = client
+            .try_get(urls.clone().into_iter(), &RetryPolicy::none())
+            .await
+        else {
+            panic!("expected mirror errors");
+        };
+
+        assert_eq!(errors.len(), 2);
+        for (error, url) in errors.iter().zip(&urls) {
+            assert_eq!(&error.url, url);
+        }
+        let kinds: Vec<_> = errors.iter().map(|error| error.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                FailureKind::Status(StatusCode::SERVICE_UNAVAILABLE),
+                FailureKind::Connect
+            ]
+        );
+    }
+
+    #[test]
+    async fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((100, Some(200)))
+        );
+        assert_eq!(parse_content_range("bytes 100-199/*"), Some((100, None)));
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+}